@@ -0,0 +1,301 @@
+// ABI definitions of the structures handed to the AFD driver and to
+// `NtCreateFile`. They only use fixed-width integers and pointer-sized
+// handles, so their layout is the same on every host with the same pointer
+// width and can be checked without a Windows machine.
+
+use std::ffi::c_void;
+#[cfg(any(windows, test))]
+use std::mem::size_of_val;
+use std::mem::{align_of, offset_of, size_of};
+#[cfg(any(windows, test))]
+use std::ptr::{addr_of_mut, null_mut};
+#[cfg(any(windows, test))]
+use std::slice;
+
+/// A kernel object handle, which is pointer sized on every Windows target.
+#[allow(clippy::upper_case_acronyms)]
+pub(crate) type HANDLE = usize;
+
+/// Only the `QuadPart` of the Windows `LARGE_INTEGER` union. The union is 8
+/// byte aligned even on 32-bit Windows, which is not true of `i64` on every
+/// 32-bit host.
+#[allow(non_snake_case)]
+#[repr(C, align(8))]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct LARGE_INTEGER {
+    pub QuadPart: i64,
+}
+
+#[allow(non_snake_case)]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct AFD_POLL_HANDLE_INFO {
+    pub Handle: HANDLE,
+    pub Events: u32,
+    pub Status: i32,
+}
+
+#[allow(non_snake_case)]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct AFD_POLL_INFO {
+    pub Timeout: LARGE_INTEGER,
+    pub NumberOfHandles: u32,
+    pub Exclusive: u32,
+    pub Handles: [AFD_POLL_HANDLE_INFO; 1],
+}
+
+#[cfg(windows)]
+impl AFD_POLL_INFO {
+    pub(crate) fn new() -> AFD_POLL_INFO {
+        AFD_POLL_INFO {
+            Timeout: LARGE_INTEGER::default(),
+            NumberOfHandles: 1,
+            Exclusive: 0,
+            Handles: [AFD_POLL_HANDLE_INFO {
+                Handle: 0,
+                Events: 0,
+                Status: 0,
+            }],
+        }
+    }
+}
+
+/// An `AFD_POLL_INFO` with room for any number of handles, as a poll of
+/// several sockets at once takes. The driver reads the handles to poll from
+/// it and writes back those with events.
+#[cfg(any(windows, test))]
+pub(crate) struct AfdPollInfoBuf {
+    // Words as aligned as the structure.
+    words: Vec<LARGE_INTEGER>,
+    handles: usize,
+}
+
+#[cfg(any(windows, test))]
+impl AfdPollInfoBuf {
+    /// Creates a zeroed buffer with room for `handles` handles, one at
+    /// least.
+    pub(crate) fn new(handles: usize) -> AfdPollInfoBuf {
+        let handles = handles.max(1);
+        let size = AfdPollInfoBuf::size_for(handles);
+        let words = size.div_ceil(size_of::<LARGE_INTEGER>());
+        let mut buf = AfdPollInfoBuf {
+            words: vec![LARGE_INTEGER::default(); words],
+            handles,
+        };
+        buf.info_mut().NumberOfHandles = handles as u32;
+        buf
+    }
+
+    fn size_for(handles: usize) -> usize {
+        size_of::<AFD_POLL_INFO>() + (handles - 1) * size_of::<AFD_POLL_HANDLE_INFO>()
+    }
+
+    /// Returns the size of the structure, as handed to the driver.
+    pub(crate) fn size(&self) -> usize {
+        AfdPollInfoBuf::size_for(self.handles)
+    }
+
+    pub(crate) fn as_mut_ptr(&mut self) -> *mut AFD_POLL_INFO {
+        self.words.as_mut_ptr() as *mut AFD_POLL_INFO
+    }
+
+    pub(crate) fn info_mut(&mut self) -> &mut AFD_POLL_INFO {
+        unsafe { &mut *self.as_mut_ptr() }
+    }
+
+    /// Returns every handle the buffer has room for.
+    pub(crate) fn handles_mut(&mut self) -> &mut [AFD_POLL_HANDLE_INFO] {
+        unsafe {
+            let first = addr_of_mut!((*self.as_mut_ptr()).Handles) as *mut AFD_POLL_HANDLE_INFO;
+            slice::from_raw_parts_mut(first, self.handles)
+        }
+    }
+
+    /// Returns the handles the driver reported events for, once the poll
+    /// has completed.
+    pub(crate) fn reported(&mut self) -> &[AFD_POLL_HANDLE_INFO] {
+        let reported = self.info_mut().NumberOfHandles as usize;
+        let handles = self.handles_mut();
+        &handles[..reported.min(handles.len())]
+    }
+}
+
+#[allow(non_snake_case)]
+#[repr(C)]
+#[derive(Debug)]
+pub(crate) struct UNICODE_STRING {
+    pub Length: u16,
+    pub MaximumLength: u16,
+    pub Buffer: *mut u16,
+}
+
+unsafe impl Send for UNICODE_STRING {}
+unsafe impl Sync for UNICODE_STRING {}
+
+#[cfg(any(windows, test))]
+impl UNICODE_STRING {
+    /// Describes the nul terminated wide string `buffer`, like
+    /// `RTL_CONSTANT_STRING` does. `Length` excludes the terminator while
+    /// `MaximumLength` includes it.
+    pub(crate) fn new(buffer: &[u16]) -> UNICODE_STRING {
+        assert_eq!(buffer.last(), Some(&0), "string is not nul terminated");
+
+        UNICODE_STRING {
+            Length: ((buffer.len() - 1) * size_of::<u16>()) as u16,
+            MaximumLength: size_of_val(buffer) as u16,
+            Buffer: buffer.as_ptr() as *mut _,
+        }
+    }
+}
+
+#[allow(non_snake_case)]
+#[repr(C)]
+#[derive(Debug)]
+pub(crate) struct OBJECT_ATTRIBUTES {
+    pub Length: u32,
+    pub RootDirectory: HANDLE,
+    pub ObjectName: *mut UNICODE_STRING,
+    pub Attributes: u32,
+    pub SecurityDescriptor: *mut c_void,
+    pub SecurityQualityOfService: *mut c_void,
+}
+
+unsafe impl Send for OBJECT_ATTRIBUTES {}
+unsafe impl Sync for OBJECT_ATTRIBUTES {}
+
+#[cfg(any(windows, test))]
+impl OBJECT_ATTRIBUTES {
+    pub(crate) fn new(object_name: &UNICODE_STRING) -> OBJECT_ATTRIBUTES {
+        OBJECT_ATTRIBUTES {
+            Length: size_of::<OBJECT_ATTRIBUTES>() as u32,
+            RootDirectory: 0,
+            ObjectName: object_name as *const _ as *mut _,
+            Attributes: 0,
+            SecurityDescriptor: null_mut(),
+            SecurityQualityOfService: null_mut(),
+        }
+    }
+}
+
+// Layouts as declared by the Windows SDK and wepoll's afd.h.
+#[cfg(target_pointer_width = "64")]
+const _: () = {
+    assert!(size_of::<LARGE_INTEGER>() == 8 && align_of::<LARGE_INTEGER>() == 8);
+
+    assert!(size_of::<AFD_POLL_HANDLE_INFO>() == 16);
+    assert!(offset_of!(AFD_POLL_HANDLE_INFO, Handle) == 0);
+    assert!(offset_of!(AFD_POLL_HANDLE_INFO, Events) == 8);
+    assert!(offset_of!(AFD_POLL_HANDLE_INFO, Status) == 12);
+
+    assert!(size_of::<AFD_POLL_INFO>() == 32);
+    assert!(offset_of!(AFD_POLL_INFO, Timeout) == 0);
+    assert!(offset_of!(AFD_POLL_INFO, NumberOfHandles) == 8);
+    assert!(offset_of!(AFD_POLL_INFO, Exclusive) == 12);
+    assert!(offset_of!(AFD_POLL_INFO, Handles) == 16);
+
+    assert!(size_of::<UNICODE_STRING>() == 16);
+    assert!(offset_of!(UNICODE_STRING, Length) == 0);
+    assert!(offset_of!(UNICODE_STRING, MaximumLength) == 2);
+    assert!(offset_of!(UNICODE_STRING, Buffer) == 8);
+
+    assert!(size_of::<OBJECT_ATTRIBUTES>() == 48);
+    assert!(offset_of!(OBJECT_ATTRIBUTES, Length) == 0);
+    assert!(offset_of!(OBJECT_ATTRIBUTES, RootDirectory) == 8);
+    assert!(offset_of!(OBJECT_ATTRIBUTES, ObjectName) == 16);
+    assert!(offset_of!(OBJECT_ATTRIBUTES, Attributes) == 24);
+    assert!(offset_of!(OBJECT_ATTRIBUTES, SecurityDescriptor) == 32);
+    assert!(offset_of!(OBJECT_ATTRIBUTES, SecurityQualityOfService) == 40);
+};
+
+#[cfg(target_pointer_width = "32")]
+const _: () = {
+    assert!(size_of::<LARGE_INTEGER>() == 8 && align_of::<LARGE_INTEGER>() == 8);
+
+    assert!(size_of::<AFD_POLL_HANDLE_INFO>() == 12);
+    assert!(offset_of!(AFD_POLL_HANDLE_INFO, Handle) == 0);
+    assert!(offset_of!(AFD_POLL_HANDLE_INFO, Events) == 4);
+    assert!(offset_of!(AFD_POLL_HANDLE_INFO, Status) == 8);
+
+    assert!(size_of::<AFD_POLL_INFO>() == 32);
+    assert!(offset_of!(AFD_POLL_INFO, Timeout) == 0);
+    assert!(offset_of!(AFD_POLL_INFO, NumberOfHandles) == 8);
+    assert!(offset_of!(AFD_POLL_INFO, Exclusive) == 12);
+    assert!(offset_of!(AFD_POLL_INFO, Handles) == 16);
+
+    assert!(size_of::<UNICODE_STRING>() == 8);
+    assert!(offset_of!(UNICODE_STRING, Length) == 0);
+    assert!(offset_of!(UNICODE_STRING, MaximumLength) == 2);
+    assert!(offset_of!(UNICODE_STRING, Buffer) == 4);
+
+    assert!(size_of::<OBJECT_ATTRIBUTES>() == 24);
+    assert!(offset_of!(OBJECT_ATTRIBUTES, Length) == 0);
+    assert!(offset_of!(OBJECT_ATTRIBUTES, RootDirectory) == 4);
+    assert!(offset_of!(OBJECT_ATTRIBUTES, ObjectName) == 8);
+    assert!(offset_of!(OBJECT_ATTRIBUTES, Attributes) == 12);
+    assert!(offset_of!(OBJECT_ATTRIBUTES, SecurityDescriptor) == 16);
+    assert!(offset_of!(OBJECT_ATTRIBUTES, SecurityQualityOfService) == 20);
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unicode_string_lengths() {
+        let name: Vec<u16> = "\\Device\\Afd\\Wepoll"
+            .encode_utf16()
+            .chain(Some(0))
+            .collect();
+        let string = UNICODE_STRING::new(&name);
+
+        assert_eq!(string.Length, 36);
+        assert_eq!(string.MaximumLength, 38);
+        assert_eq!(string.Buffer as *const u16, name.as_ptr());
+    }
+
+    #[test]
+    #[should_panic]
+    fn unicode_string_requires_terminator() {
+        UNICODE_STRING::new(&[0x41, 0x42]);
+    }
+
+    #[test]
+    fn poll_info_buffer_holds_every_handle() {
+        let mut buf = AfdPollInfoBuf::new(3);
+        assert_eq!(
+            buf.size(),
+            size_of::<AFD_POLL_INFO>() + 2 * size_of::<AFD_POLL_HANDLE_INFO>()
+        );
+        assert_eq!(buf.as_mut_ptr() as usize % align_of::<AFD_POLL_INFO>(), 0);
+        assert_eq!(buf.info_mut().NumberOfHandles, 3);
+
+        let start = buf.as_mut_ptr() as usize;
+        for (i, handle) in buf.handles_mut().iter_mut().enumerate() {
+            let offset = handle as *mut _ as usize - start;
+            assert_eq!(
+                offset,
+                offset_of!(AFD_POLL_INFO, Handles) + i * size_of::<AFD_POLL_HANDLE_INFO>()
+            );
+            handle.Handle = 10 + i;
+        }
+        assert!(buf.words.len() * size_of::<LARGE_INTEGER>() >= buf.size());
+
+        // The driver writes back fewer handles than it was given.
+        buf.info_mut().NumberOfHandles = 1;
+        assert_eq!(buf.reported().len(), 1);
+        assert_eq!(buf.reported()[0].Handle, 10);
+        assert_eq!(AfdPollInfoBuf::new(0).handles_mut().len(), 1);
+    }
+
+    #[test]
+    fn object_attributes_point_at_name() {
+        let name = [0x41, 0];
+        let string = UNICODE_STRING::new(&name);
+        let attributes = OBJECT_ATTRIBUTES::new(&string);
+
+        assert_eq!(attributes.Length as usize, size_of::<OBJECT_ATTRIBUTES>());
+        assert_eq!(attributes.ObjectName as *const _, &string as *const _);
+        assert_eq!(attributes.RootDirectory, 0);
+    }
+}
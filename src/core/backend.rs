@@ -0,0 +1,202 @@
+// Choice of the mechanism a selector waits with. On Windows AFD polling is
+// preferred, but the AFD device may be missing, as it is on some hardened
+// systems and under Wine, in which case `WSAPoll` is used instead.
+
+#[cfg(any(windows, test))]
+use crate::core::afd::HANDLE;
+#[cfg(any(windows, test))]
+use std::{error, fmt, io};
+
+/// The device wepoll opens its helper handles on. Any name below
+/// `\Device\Afd` opens the AFD driver.
+pub(crate) const DEFAULT_AFD_DEVICE: &str = "\\Device\\Afd\\Wepoll";
+
+/// The mechanism a selector waits for readiness with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// `epoll`, on Linux.
+    Epoll,
+    /// AFD poll operations completed through an I/O completion port, on
+    /// Windows.
+    Afd,
+    /// `WSAPoll`, on Windows when the AFD device cannot be opened.
+    WsaPoll,
+}
+
+/// The layer AFD helper handles are opened through.
+#[cfg(any(windows, test))]
+pub(crate) trait AfdDevice {
+    /// Opens the AFD device at the NT path `path`.
+    fn open(&mut self, path: &str) -> io::Result<HANDLE>;
+
+    /// Closes a handle returned by `open`.
+    fn close(&mut self, handle: HANDLE);
+}
+
+/// Why AFD polling cannot be used.
+#[cfg(any(windows, test))]
+#[derive(Debug)]
+pub(crate) enum AfdUnavailable {
+    /// The configured device path is not an NT device path.
+    InvalidPath { path: String },
+    /// The device could not be opened.
+    Open { path: String, error: io::Error },
+}
+
+#[cfg(any(windows, test))]
+impl fmt::Display for AfdUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AfdUnavailable::InvalidPath { path } => write!(
+                f,
+                "AFD device path {:?} is not an NT device path such as {:?}",
+                path, DEFAULT_AFD_DEVICE
+            ),
+            AfdUnavailable::Open { path, error } => {
+                write!(f, "AFD device {:?} cannot be opened: {}", path, error)
+            }
+        }
+    }
+}
+
+#[cfg(any(windows, test))]
+impl error::Error for AfdUnavailable {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            AfdUnavailable::Open { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(any(windows, test))]
+impl From<AfdUnavailable> for io::Error {
+    fn from(err: AfdUnavailable) -> io::Error {
+        let kind = match err {
+            AfdUnavailable::InvalidPath { .. } => io::ErrorKind::InvalidInput,
+            AfdUnavailable::Open { ref error, .. } => error.kind(),
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+/// Checks that AFD helper handles can be opened on the device at `path`.
+#[cfg(any(windows, test))]
+pub(crate) fn probe_afd<D: AfdDevice>(device: &mut D, path: &str) -> Result<(), AfdUnavailable> {
+    if !path.starts_with("\\Device\\") || path.contains('\0') {
+        return Err(AfdUnavailable::InvalidPath {
+            path: path.to_owned(),
+        });
+    }
+
+    match device.open(path) {
+        Ok(handle) => {
+            device.close(handle);
+            Ok(())
+        }
+        Err(error) => Err(AfdUnavailable::Open {
+            path: path.to_owned(),
+            error,
+        }),
+    }
+}
+
+/// Picks the backend of a new selector, AFD unless the probe fails.
+#[cfg(any(windows, test))]
+pub(crate) fn choose_backend<D: AfdDevice>(device: &mut D, path: &str) -> Backend {
+    match probe_afd(device, path) {
+        Ok(()) => Backend::Afd,
+        Err(_) => Backend::WsaPoll,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A device layer which only knows the devices it has been given.
+    #[derive(Default)]
+    struct FakeDevice {
+        devices: Vec<&'static str>,
+        opened: Vec<String>,
+        open_handles: Vec<HANDLE>,
+    }
+
+    impl AfdDevice for FakeDevice {
+        fn open(&mut self, path: &str) -> io::Result<HANDLE> {
+            self.opened.push(path.to_owned());
+            if !self.devices.contains(&path) {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "object path not found",
+                ));
+            }
+            let handle = 4 * (self.opened.len() + 1);
+            self.open_handles.push(handle);
+            Ok(handle)
+        }
+
+        fn close(&mut self, handle: HANDLE) {
+            let index = self.open_handles.iter().position(|&h| h == handle);
+            self.open_handles
+                .remove(index.expect("unknown handle closed"));
+        }
+    }
+
+    #[test]
+    fn afd_is_chosen_when_the_device_opens() {
+        let mut device = FakeDevice {
+            devices: vec![DEFAULT_AFD_DEVICE],
+            ..FakeDevice::default()
+        };
+        assert_eq!(
+            choose_backend(&mut device, DEFAULT_AFD_DEVICE),
+            Backend::Afd
+        );
+        assert_eq!(device.opened, [DEFAULT_AFD_DEVICE]);
+        assert!(device.open_handles.is_empty());
+    }
+
+    #[test]
+    fn missing_device_falls_back_to_wsapoll() {
+        let mut device = FakeDevice::default();
+        assert_eq!(
+            choose_backend(&mut device, DEFAULT_AFD_DEVICE),
+            Backend::WsaPoll
+        );
+
+        let err = probe_afd(&mut device, DEFAULT_AFD_DEVICE).unwrap_err();
+        assert!(err.to_string().contains("\\\\Device\\\\Afd\\\\Wepoll"));
+        assert_eq!(io::Error::from(err).kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn configured_device_is_opened() {
+        let mut device = FakeDevice {
+            devices: vec!["\\Device\\Afd\\Custom"],
+            ..FakeDevice::default()
+        };
+        assert_eq!(
+            choose_backend(&mut device, DEFAULT_AFD_DEVICE),
+            Backend::WsaPoll
+        );
+        assert_eq!(
+            choose_backend(&mut device, "\\Device\\Afd\\Custom"),
+            Backend::Afd
+        );
+        assert!(device.open_handles.is_empty());
+    }
+
+    #[test]
+    fn invalid_paths_are_never_opened() {
+        let mut device = FakeDevice::default();
+        for &path in ["", "Afd", "\\??\\C:\\afd", "\\Device\\Afd\0"].iter() {
+            match probe_afd(&mut device, path) {
+                Err(AfdUnavailable::InvalidPath { .. }) => {}
+                other => panic!("{:?}: {:?}", path, other),
+            }
+            assert_eq!(choose_backend(&mut device, path), Backend::WsaPoll);
+        }
+        assert!(device.opened.is_empty());
+    }
+}
@@ -0,0 +1,219 @@
+// Resolution of the base socket the AFD driver has to be handed. Layered
+// service providers (LSPs) wrap the sockets created by the base provider,
+// and only the base socket can be polled. This follows
+// `ws_get_base_socket` in wepoll's ws.c, the ioctl itself is left to the
+// caller so that the fallback order can be checked on any host.
+
+use crate::core::afd::HANDLE;
+use std::{error, fmt, io};
+
+pub(crate) const SIO_BASE_HANDLE: u32 = 0x48000022;
+pub(crate) const SIO_BSP_HANDLE_SELECT: u32 = 0x4800001C;
+pub(crate) const SIO_BSP_HANDLE_POLL: u32 = 0x4800001D;
+
+pub(crate) const WSAENOTSOCK: i32 = 10038;
+
+// Providers hand out the socket of the provider below them, so a chain this
+// long can only be a loop.
+const MAX_LAYERS: usize = 16;
+
+/// Why no base socket could be found for a socket.
+#[derive(Debug)]
+pub(crate) enum BaseSocketError {
+    /// The handle is not a socket at all.
+    NotASocket { socket: HANDLE },
+    /// `SIO_BASE_HANDLE` failed and neither `SIO_BSP_HANDLE_POLL` nor
+    /// `SIO_BSP_HANDLE_SELECT` led to another socket, a layered service
+    /// provider hides the base socket.
+    Hidden { socket: HANDLE, error: io::Error },
+    /// The providers kept handing out new sockets.
+    TooManyLayers { socket: HANDLE },
+}
+
+impl fmt::Display for BaseSocketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BaseSocketError::NotASocket { socket } => {
+                write!(f, "handle {:#x} is not a socket", socket)
+            }
+            BaseSocketError::Hidden { socket, error } => write!(
+                f,
+                "socket {:#x} cannot be polled through AFD, a layered service \
+                 provider hides its base socket (SIO_BASE_HANDLE failed: {})",
+                socket, error
+            ),
+            BaseSocketError::TooManyLayers { socket } => write!(
+                f,
+                "socket {:#x} cannot be polled through AFD, its layered \
+                 service providers nest more than {} deep",
+                socket, MAX_LAYERS
+            ),
+        }
+    }
+}
+
+impl error::Error for BaseSocketError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            BaseSocketError::Hidden { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<BaseSocketError> for io::Error {
+    fn from(err: BaseSocketError) -> io::Error {
+        let kind = match err {
+            BaseSocketError::NotASocket { .. } => io::ErrorKind::InvalidInput,
+            _ => io::ErrorKind::Unsupported,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+/// Returns the base socket of `socket`. `ioctl` issues the given
+/// `SIO_*_HANDLE` control code on a socket and returns the socket it yields.
+pub(crate) fn resolve_base_socket<F>(
+    socket: HANDLE,
+    mut ioctl: F,
+) -> Result<HANDLE, BaseSocketError>
+where
+    F: FnMut(HANDLE, u32) -> io::Result<HANDLE>,
+{
+    let mut socket = socket;
+
+    for _ in 0..MAX_LAYERS {
+        let error = match ioctl(socket, SIO_BASE_HANDLE) {
+            Ok(base_socket) => return Ok(base_socket),
+            Err(error) => error,
+        };
+        if error.raw_os_error() == Some(WSAENOTSOCK) {
+            return Err(BaseSocketError::NotASocket { socket });
+        }
+
+        // Even though Microsoft documentation clearly states that LSPs
+        // should never intercept SIO_BASE_HANDLE, some do anyway. Ask the
+        // provider for the socket it would hand to poll() or select()
+        // instead and start over with that one.
+        let next = [SIO_BSP_HANDLE_POLL, SIO_BSP_HANDLE_SELECT]
+            .iter()
+            .filter_map(|&code| ioctl(socket, code).ok())
+            .find(|&bsp_socket| bsp_socket != socket);
+
+        match next {
+            Some(bsp_socket) => socket = bsp_socket,
+            None => return Err(BaseSocketError::Hidden { socket, error }),
+        }
+    }
+
+    Err(BaseSocketError::TooManyLayers { socket })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const WSAEOPNOTSUPP: i32 = 10045;
+
+    /// A WSAIoctl stand-in which answers from a table and records every call.
+    struct Ioctl {
+        answers: HashMap<(HANDLE, u32), HANDLE>,
+        calls: Vec<(HANDLE, u32)>,
+    }
+
+    impl Ioctl {
+        fn new(answers: &[(HANDLE, u32, HANDLE)]) -> Ioctl {
+            Ioctl {
+                answers: answers
+                    .iter()
+                    .map(|&(socket, code, answer)| ((socket, code), answer))
+                    .collect(),
+                calls: Vec::new(),
+            }
+        }
+
+        fn resolve(&mut self, socket: HANDLE) -> Result<HANDLE, BaseSocketError> {
+            resolve_base_socket(socket, |socket, code| {
+                self.calls.push((socket, code));
+                match self.answers.get(&(socket, code)) {
+                    Some(&answer) => Ok(answer),
+                    None if socket == 0 => Err(io::Error::from_raw_os_error(WSAENOTSOCK)),
+                    None => Err(io::Error::from_raw_os_error(WSAEOPNOTSUPP)),
+                }
+            })
+        }
+    }
+
+    #[test]
+    fn base_handle_is_asked_first() {
+        let mut ioctl = Ioctl::new(&[(10, SIO_BASE_HANDLE, 1)]);
+        assert_eq!(ioctl.resolve(10).unwrap(), 1);
+        assert_eq!(ioctl.calls, [(10, SIO_BASE_HANDLE)]);
+    }
+
+    #[test]
+    fn intercepting_providers_are_bypassed() {
+        // The provider of socket 10 swallows SIO_BASE_HANDLE but tells the
+        // poll socket, whose provider answers properly.
+        let mut ioctl = Ioctl::new(&[(10, SIO_BSP_HANDLE_POLL, 20), (20, SIO_BASE_HANDLE, 1)]);
+        assert_eq!(ioctl.resolve(10).unwrap(), 1);
+        assert_eq!(
+            ioctl.calls,
+            [
+                (10, SIO_BASE_HANDLE),
+                (10, SIO_BSP_HANDLE_POLL),
+                (20, SIO_BASE_HANDLE)
+            ]
+        );
+
+        // Handing out the socket itself does not count, the select socket is
+        // tried next.
+        let mut ioctl = Ioctl::new(&[
+            (10, SIO_BSP_HANDLE_POLL, 10),
+            (10, SIO_BSP_HANDLE_SELECT, 30),
+            (30, SIO_BASE_HANDLE, 1),
+        ]);
+        assert_eq!(ioctl.resolve(10).unwrap(), 1);
+        assert_eq!(
+            ioctl.calls,
+            [
+                (10, SIO_BASE_HANDLE),
+                (10, SIO_BSP_HANDLE_POLL),
+                (10, SIO_BSP_HANDLE_SELECT),
+                (30, SIO_BASE_HANDLE)
+            ]
+        );
+    }
+
+    #[test]
+    fn unresolvable_sockets_are_explained() {
+        match Ioctl::new(&[]).resolve(0) {
+            Err(BaseSocketError::NotASocket { socket: 0 }) => {}
+            other => panic!("{:?}", other),
+        }
+
+        let err = Ioctl::new(&[(10, SIO_BSP_HANDLE_SELECT, 10)])
+            .resolve(10)
+            .unwrap_err();
+        match err {
+            BaseSocketError::Hidden {
+                socket: 10,
+                ref error,
+            } => {
+                assert_eq!(error.raw_os_error(), Some(WSAEOPNOTSUPP))
+            }
+            ref other => panic!("{:?}", other),
+        }
+        assert!(err.to_string().contains("layered service provider"));
+        assert_eq!(io::Error::from(err).kind(), io::ErrorKind::Unsupported);
+
+        // Two providers pointing at each other.
+        let mut ioctl = Ioctl::new(&[(10, SIO_BSP_HANDLE_POLL, 20), (20, SIO_BSP_HANDLE_POLL, 10)]);
+        match ioctl.resolve(10) {
+            Err(BaseSocketError::TooManyLayers { .. }) => {}
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(ioctl.calls.len(), 2 * MAX_LAYERS);
+    }
+}
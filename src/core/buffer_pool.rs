@@ -0,0 +1,361 @@
+// Buffers for completion based I/O. The operating system writes into the
+// buffer of a read until the operation completes, so it must neither move
+// nor be freed before then. Pooled buffers live on the heap at a fixed
+// address, are lent to operations by value and go back to their pool when
+// dropped, whether the operation completed or was cancelled.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Size of the buffers of a pool by default.
+const DEFAULT_BUFFER_SIZE: usize = 4096;
+/// Memory a pool holds at most by default, 4 MiB.
+const DEFAULT_MAX_BYTES: usize = 4 << 20;
+
+/// The limit of a pool which refused to lend another buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Exhausted {
+    /// As many buffers as allowed are lent out.
+    Outstanding(usize),
+    /// Another buffer would take the pool over its memory limit.
+    Memory(usize),
+}
+
+impl fmt::Display for Exhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Exhausted::Outstanding(max) => {
+                write!(f, "all {} buffers of the pool are lent out", max)
+            }
+            Exhausted::Memory(max) => {
+                write!(f, "the buffer pool holds its limit of {} bytes", max)
+            }
+        }
+    }
+}
+
+impl Error for Exhausted {}
+
+impl From<Exhausted> for io::Error {
+    fn from(err: Exhausted) -> io::Error {
+        // Buffers come back as operations complete.
+        io::Error::new(io::ErrorKind::WouldBlock, err)
+    }
+}
+
+/// Counters of a `BufferPool`, as returned by `BufferPool::stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Buffers allocated, lent out or free.
+    pub allocated: usize,
+    /// Buffers lent out.
+    pub outstanding: usize,
+    /// Memory held by the allocated buffers.
+    pub allocated_bytes: usize,
+    /// Buffers lent out so far.
+    pub acquired: u64,
+    /// Requests refused because of the limit on lent buffers.
+    pub exhausted_outstanding: u64,
+    /// Requests refused because of the memory limit.
+    pub exhausted_memory: u64,
+}
+
+/// Configures a [`BufferPool`] before creating it.
+#[derive(Debug, Clone)]
+pub struct BufferPoolBuilder {
+    buffer_size: usize,
+    max_bytes: usize,
+    max_outstanding: usize,
+}
+
+impl BufferPoolBuilder {
+    /// Returns a builder with the default configuration.
+    pub fn new() -> BufferPoolBuilder {
+        BufferPoolBuilder::default()
+    }
+
+    /// Sets the size of every buffer, 4 KiB by default.
+    pub fn buffer_size(mut self, bytes: usize) -> BufferPoolBuilder {
+        self.buffer_size = bytes;
+        self
+    }
+
+    /// Sets how much memory the buffers take at most, lent out or free,
+    /// 4 MiB by default.
+    pub fn max_bytes(mut self, bytes: usize) -> BufferPoolBuilder {
+        self.max_bytes = bytes;
+        self
+    }
+
+    /// Sets how many buffers are lent out at once at most, only the memory
+    /// limit applies by default.
+    pub fn max_outstanding(mut self, buffers: usize) -> BufferPoolBuilder {
+        self.max_outstanding = buffers;
+        self
+    }
+
+    /// Creates an empty pool with this configuration.
+    pub fn build(&self) -> io::Result<BufferPool> {
+        if self.buffer_size == 0 {
+            return Err(invalid("buffers must hold at least one byte"));
+        }
+        if self.max_bytes < self.buffer_size || self.max_outstanding == 0 {
+            return Err(invalid("the pool must allow at least one buffer"));
+        }
+
+        Ok(BufferPool {
+            shared: Arc::new(Shared {
+                buffer_size: self.buffer_size,
+                max_bytes: self.max_bytes,
+                max_outstanding: self.max_outstanding,
+                state: Mutex::new(State::default()),
+            }),
+        })
+    }
+}
+
+impl Default for BufferPoolBuilder {
+    fn default() -> BufferPoolBuilder {
+        BufferPoolBuilder {
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            max_bytes: DEFAULT_MAX_BYTES,
+            max_outstanding: usize::MAX,
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    free: Vec<Box<[u8]>>,
+    stats: PoolStats,
+}
+
+struct Shared {
+    buffer_size: usize,
+    max_bytes: usize,
+    max_outstanding: usize,
+    state: Mutex<State>,
+}
+
+impl Shared {
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A pool of equally sized buffers for reads and writes in flight.
+///
+/// Clones share the pool, buffers can be acquired and dropped on any thread.
+#[derive(Clone)]
+pub struct BufferPool {
+    shared: Arc<Shared>,
+}
+
+impl BufferPool {
+    /// Lends a buffer, reusing a free one if there is any. Its contents are
+    /// what the last user left, a new buffer is zeroed.
+    ///
+    /// Fails with `WouldBlock` if a limit of the pool is reached, until
+    /// buffers are given back.
+    pub fn acquire(&self) -> io::Result<PooledBuf> {
+        let shared = &self.shared;
+        let mut state = shared.state();
+
+        if state.stats.outstanding == shared.max_outstanding {
+            state.stats.exhausted_outstanding += 1;
+            return Err(Exhausted::Outstanding(shared.max_outstanding).into());
+        }
+        let data = match state.free.pop() {
+            Some(data) => data,
+            None if state.stats.allocated_bytes + shared.buffer_size > shared.max_bytes => {
+                state.stats.exhausted_memory += 1;
+                return Err(Exhausted::Memory(shared.max_bytes).into());
+            }
+            None => {
+                state.stats.allocated += 1;
+                state.stats.allocated_bytes += shared.buffer_size;
+                vec![0; shared.buffer_size].into_boxed_slice()
+            }
+        };
+        state.stats.outstanding += 1;
+        state.stats.acquired += 1;
+
+        Ok(PooledBuf {
+            len: data.len(),
+            data: Some(data),
+            pool: shared.clone(),
+        })
+    }
+
+    /// Returns the size of the buffers.
+    pub fn buffer_size(&self) -> usize {
+        self.shared.buffer_size
+    }
+
+    /// Returns the counters of the pool.
+    pub fn stats(&self) -> PoolStats {
+        self.shared.state().stats
+    }
+}
+
+impl fmt::Debug for BufferPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BufferPool")
+            .field("buffer_size", &self.shared.buffer_size)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+/// A buffer lent by a `BufferPool`, given back when dropped.
+///
+/// The bytes stay at the same address for as long as the buffer lives,
+/// however it is moved.
+pub struct PooledBuf {
+    // Only `None` while being given back.
+    data: Option<Box<[u8]>>,
+    len: usize,
+    pool: Arc<Shared>,
+}
+
+impl PooledBuf {
+    /// Returns the size of the whole buffer.
+    pub fn capacity(&self) -> usize {
+        self.pool.buffer_size
+    }
+
+    /// Sets how many bytes of the buffer are used, like the length of the
+    /// data to write. A new buffer uses all of them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `len` is larger than the capacity.
+    pub fn set_len(&mut self, len: usize) {
+        assert!(len <= self.capacity(), "length beyond the buffer");
+        self.len = len;
+    }
+}
+
+impl Deref for PooledBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data.as_ref().unwrap()[..self.len]
+    }
+}
+
+impl DerefMut for PooledBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.data.as_mut().unwrap()[..self.len]
+    }
+}
+
+impl fmt::Debug for PooledBuf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PooledBuf")
+            .field("len", &self.len)
+            .field("capacity", &self.capacity())
+            .finish()
+    }
+}
+
+impl Drop for PooledBuf {
+    fn drop(&mut self) {
+        if let Some(data) = self.data.take() {
+            let mut state = self.pool.state();
+            state.stats.outstanding -= 1;
+            state.free.push(data);
+        }
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(max_bytes: usize, max_outstanding: usize) -> BufferPool {
+        BufferPoolBuilder::new()
+            .buffer_size(16)
+            .max_bytes(max_bytes)
+            .max_outstanding(max_outstanding)
+            .build()
+            .unwrap()
+    }
+
+    fn exhausted(result: io::Result<PooledBuf>) -> Exhausted {
+        let err = result.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        *err.get_ref().unwrap().downcast_ref::<Exhausted>().unwrap()
+    }
+
+    #[test]
+    fn buffers_are_reused_where_they_are() {
+        let pool = pool(64, 4);
+
+        let mut buf = pool.acquire().unwrap();
+        assert_eq!(buf.len(), 16);
+        buf[..5].copy_from_slice(b"hello");
+        let address = buf.as_ptr();
+        // Moving the buffer, like into an operation, leaves the bytes.
+        let moved = Box::new(buf);
+        assert_eq!(moved.as_ptr(), address);
+        drop(moved);
+
+        let again = pool.acquire().unwrap();
+        assert_eq!(again.as_ptr(), address);
+        assert_eq!(&again[..5], b"hello");
+        assert_eq!(
+            pool.stats(),
+            PoolStats {
+                allocated: 1,
+                outstanding: 1,
+                allocated_bytes: 16,
+                acquired: 2,
+                ..PoolStats::default()
+            }
+        );
+    }
+
+    #[test]
+    fn limits_are_enforced_and_counted() {
+        let lent = pool(64, 2);
+        let (a, _b) = (lent.acquire().unwrap(), lent.acquire().unwrap());
+        assert_eq!(exhausted(lent.acquire()), Exhausted::Outstanding(2));
+        drop(a);
+        let _c = lent.acquire().unwrap();
+
+        let memory = pool(40, 8);
+        let _held = (memory.acquire().unwrap(), memory.acquire().unwrap());
+        assert_eq!(exhausted(memory.acquire()), Exhausted::Memory(40));
+        assert_eq!(exhausted(memory.acquire()), Exhausted::Memory(40));
+
+        assert_eq!(lent.stats().exhausted_outstanding, 1);
+        assert_eq!(memory.stats().exhausted_memory, 2);
+        assert_eq!(memory.stats().allocated_bytes, 32);
+    }
+
+    #[test]
+    fn length_is_limited_by_the_capacity() {
+        let pool = pool(64, 4);
+        let mut buf = pool.acquire().unwrap();
+        buf.set_len(3);
+        assert_eq!(buf.len(), 3);
+        assert_eq!(buf.capacity(), 16);
+
+        let result = std::panic::catch_unwind(move || buf.set_len(17));
+        assert!(result.is_err());
+        // The buffer was given back while unwinding.
+        assert_eq!(pool.stats().outstanding, 0);
+
+        assert!(BufferPoolBuilder::new().buffer_size(0).build().is_err());
+        assert!(BufferPoolBuilder::new().max_bytes(1).build().is_err());
+        assert!(BufferPoolBuilder::new().max_outstanding(0).build().is_err());
+    }
+}
@@ -0,0 +1,194 @@
+use crate::core::backend::{Backend, DEFAULT_AFD_DEVICE};
+use std::io;
+
+/// Sockets sharing one AFD helper handle by default, as in wepoll.
+pub(crate) const DEFAULT_GROUP_SIZE: usize = 32;
+
+/// When the readiness of a registered socket is reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    /// Reported by every `select` for as long as the socket is ready.
+    Level,
+    /// Reported once per change of the readiness. Only available with
+    /// `epoll`.
+    Edge,
+    /// Reported once, then not again until the socket is registered anew.
+    Oneshot,
+}
+
+/// Configures a [`Selector`] before creating it.
+///
+/// Options which do not apply to the backend in use are ignored.
+///
+/// [`Selector`]: crate::Selector
+#[derive(Debug, Clone)]
+pub struct SelectorBuilder {
+    pub(crate) afd_device: String,
+    pub(crate) concurrency: u32,
+    pub(crate) group_size: usize,
+    pub(crate) capacity: usize,
+    pub(crate) trigger: Option<Trigger>,
+    pub(crate) backend: Option<Backend>,
+}
+
+impl SelectorBuilder {
+    /// Returns a builder with the default configuration.
+    pub fn new() -> SelectorBuilder {
+        SelectorBuilder::default()
+    }
+
+    /// Sets the NT path of the device AFD helper handles are opened on,
+    /// `\Device\Afd\Wepoll` by default.
+    ///
+    /// Should the device not open, the selector falls back to `WSAPoll`,
+    /// [`probe`] tells why. Only used on Windows.
+    ///
+    /// [`probe`]: SelectorBuilder::probe
+    pub fn afd_device<P: Into<String>>(mut self, path: P) -> SelectorBuilder {
+        self.afd_device = path.into();
+        self
+    }
+
+    /// Sets how many threads the completion port lets run at once, 1 by
+    /// default. Only used by the AFD backend.
+    pub fn concurrency(mut self, threads: u32) -> SelectorBuilder {
+        self.concurrency = threads;
+        self
+    }
+
+    /// Sets how many sockets share one AFD helper handle, 32 by default.
+    /// Only used by the AFD and `WSAPoll` backends.
+    pub fn group_size(mut self, sockets: usize) -> SelectorBuilder {
+        self.group_size = sockets;
+        self
+    }
+
+    /// Sets how many registrations the selector makes room for up front,
+    /// none by default. Only used by the AFD and `WSAPoll` backends.
+    pub fn capacity(mut self, registrations: usize) -> SelectorBuilder {
+        self.capacity = registrations;
+        self
+    }
+
+    /// Sets the trigger mode of every registration. By default `epoll`
+    /// triggers on edges, the AFD and `WSAPoll` backends trigger once.
+    pub fn trigger(mut self, trigger: Trigger) -> SelectorBuilder {
+        self.trigger = Some(trigger);
+        self
+    }
+
+    /// Requires a backend instead of picking the best one available.
+    /// Building fails if it is not available.
+    pub fn backend(mut self, backend: Backend) -> SelectorBuilder {
+        self.backend = Some(backend);
+        self
+    }
+
+    /// Returns the backend required with `backend`, if any. Fails if it is
+    /// not one of the backends of this platform.
+    pub(crate) fn required_backend(&self, available: &[Backend]) -> io::Result<Option<Backend>> {
+        match self.backend {
+            Some(backend) if !available.contains(&backend) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "the {:?} backend is not available on this platform",
+                    backend
+                ),
+            )),
+            backend => Ok(backend),
+        }
+    }
+
+    /// Returns the trigger mode of registrations with `backend`.
+    pub(crate) fn trigger_for(&self, backend: Backend) -> Trigger {
+        match (self.trigger, backend) {
+            (Some(trigger), _) => trigger,
+            (None, Backend::Epoll) => Trigger::Edge,
+            (None, _) => Trigger::Oneshot,
+        }
+    }
+
+    /// Checks the configuration for a selector using `backend`.
+    pub(crate) fn validate(&self, backend: Backend) -> io::Result<()> {
+        let invalid = |msg: &str| Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+
+        if self.concurrency == 0 {
+            return invalid("completion port concurrency must be at least 1");
+        }
+        if self.group_size == 0 {
+            return invalid("poll group size must be at least 1");
+        }
+        if backend != Backend::Epoll && self.trigger_for(backend) == Trigger::Edge {
+            return invalid("edge triggering is only available with epoll");
+        }
+        Ok(())
+    }
+}
+
+impl Default for SelectorBuilder {
+    fn default() -> SelectorBuilder {
+        SelectorBuilder {
+            afd_device: DEFAULT_AFD_DEVICE.to_owned(),
+            concurrency: 1,
+            group_size: DEFAULT_GROUP_SIZE,
+            capacity: 0,
+            trigger: None,
+            backend: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_are_valid_everywhere() {
+        let builder = SelectorBuilder::default();
+        for &backend in [Backend::Epoll, Backend::Afd, Backend::WsaPoll].iter() {
+            assert!(builder.validate(backend).is_ok(), "{:?}", backend);
+        }
+        assert_eq!(builder.trigger_for(Backend::Epoll), Trigger::Edge);
+        assert_eq!(builder.trigger_for(Backend::Afd), Trigger::Oneshot);
+    }
+
+    #[test]
+    fn invalid_options_are_refused() {
+        let builders = [
+            SelectorBuilder::new().concurrency(0),
+            SelectorBuilder::new().group_size(0),
+        ];
+        for builder in builders.iter() {
+            let err = builder.validate(Backend::Afd).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{:?}", builder);
+        }
+
+        let edge = SelectorBuilder::new().trigger(Trigger::Edge);
+        assert!(edge.validate(Backend::Epoll).is_ok());
+        assert!(edge.validate(Backend::Afd).is_err());
+        assert!(edge.validate(Backend::WsaPoll).is_err());
+        let level = SelectorBuilder::new().trigger(Trigger::Level);
+        assert_eq!(level.trigger_for(Backend::Afd), Trigger::Level);
+    }
+
+    #[test]
+    fn required_backend_must_be_available() {
+        let available = [Backend::Afd, Backend::WsaPoll];
+        assert_eq!(
+            SelectorBuilder::new().required_backend(&available).unwrap(),
+            None
+        );
+        assert_eq!(
+            SelectorBuilder::new()
+                .backend(Backend::WsaPoll)
+                .required_backend(&available)
+                .unwrap(),
+            Some(Backend::WsaPoll)
+        );
+        let err = SelectorBuilder::new()
+            .backend(Backend::Epoll)
+            .required_backend(&available)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    }
+}
@@ -0,0 +1,562 @@
+// The operating system services a `Port` is built on. The Windows backend
+// implements them with an I/O completion port and AFD helper handles, the
+// tests with the simulated driver below.
+
+use crate::core::afd::HANDLE;
+use crate::core::event::RawEvent;
+use crate::core::proactor::{IoCompletion, IoRequest};
+use crate::core::sock::PollResult;
+use crate::core::user_event::Post;
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// What `CancelIoEx` fails with when the operation is not in flight anymore,
+/// which it may have left since the driver checked.
+pub(crate) const ERROR_NOT_FOUND: i32 = 1168;
+
+/// A completion dequeued from the driver, translated into terms of the port.
+#[derive(Debug)]
+pub(crate) enum Completion {
+    /// The AFD poll operation started for socket `id` completed.
+    Poll { id: usize, result: PollResult },
+    /// The user event `id` has been triggered.
+    User { id: usize },
+    /// The port has been woken up, nothing is to be reported.
+    Wake,
+    /// A read or write on an associated handle completed.
+    Io(IoCompletion),
+}
+
+/// Outcome of starting an AFD poll operation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum PollStart {
+    /// The operation is in flight, its completion will be reported.
+    Pending,
+    /// The socket has been closed already, no operation was started.
+    SocketClosed,
+}
+
+pub(crate) trait Driver {
+    /// A raw completion record, the `Events` buffer is made of these.
+    type Status: RawEvent;
+
+    /// Opens an AFD helper handle associated with the completion port.
+    fn open_helper(&mut self) -> io::Result<HANDLE>;
+
+    /// Closes a helper handle opened by `open_helper`.
+    fn close_helper(&mut self, helper: HANDLE);
+
+    /// Starts polling `base_socket` for `afd_events` through `helper`. The
+    /// driver keeps the memory of the operation alive until its completion
+    /// has been dequeued, the completion is reported for `id`.
+    fn start_poll(
+        &mut self,
+        helper: HANDLE,
+        base_socket: HANDLE,
+        afd_events: u32,
+        id: usize,
+    ) -> io::Result<PollStart>;
+
+    /// Cancels the poll operation in flight for `id`. Its completion is
+    /// still reported, possibly with the events it observed before. Fails
+    /// with `ERROR_NOT_FOUND` if the poll completed in the meantime.
+    fn cancel_poll(&mut self, helper: HANDLE, id: usize) -> io::Result<()>;
+
+    /// Gets `socket` ready to be probed with zero byte reads, which fails if
+    /// their completions would not reach this driver.
+    fn prepare_zero_read(&mut self, socket: HANDLE) -> io::Result<()>;
+
+    /// Starts a zero byte receive on a socket prepared for it, which
+    /// completes like a poll for `id` once the socket is readable, with the
+    /// AFD events it stands for.
+    fn start_zero_read(&mut self, socket: HANDLE, id: usize) -> io::Result<PollStart>;
+
+    /// Cancels the zero byte receive in flight for `id`, its completion is
+    /// still reported. Fails with `ERROR_NOT_FOUND` if the receive completed
+    /// in the meantime.
+    fn cancel_zero_read(&mut self, socket: HANDLE, id: usize) -> io::Result<()>;
+
+    /// Returns the current time, which timeouts are measured against.
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    /// Waits for completions and fills `statuses` with them, returns how
+    /// many have been dequeued. A `None` timeout waits forever, a zero
+    /// timeout only dequeues what is there. The wait may end early with
+    /// nothing dequeued.
+    fn wait(
+        &mut self,
+        statuses: &mut [Self::Status],
+        timeout: Option<Duration>,
+    ) -> io::Result<usize>;
+
+    /// Translates a dequeued status by its completion key, returns `None`
+    /// for statuses which do not belong to the port.
+    fn completion(&mut self, status: &Self::Status) -> Option<Completion>;
+
+    /// Returns what user events post their completions with.
+    fn poster(&self) -> Arc<dyn Post>;
+
+    /// Associates `handle` with the port for completion based I/O.
+    fn associate(&mut self, handle: HANDLE) -> io::Result<()>;
+
+    /// Starts a read or write on an associated handle. The driver owns the
+    /// request until its completion has been dequeued.
+    fn submit(&mut self, handle: HANDLE, request: IoRequest) -> io::Result<()>;
+
+    /// Cancels every read and write in flight, their completions are still
+    /// reported.
+    fn cancel_ios(&mut self) -> io::Result<()>;
+
+    /// Returns true while the completion of a read or write is outstanding.
+    fn ios_in_flight(&self) -> bool;
+}
+
+#[cfg(test)]
+pub(crate) mod sim {
+    use super::*;
+    use crate::core::key::Key;
+    use crate::core::strategy::ZERO_BYTE_READ_EVENTS;
+    use crate::core::translate::AFD_POLL_LOCAL_CLOSE;
+    use std::cell::RefCell;
+    use std::collections::{HashMap, HashSet, VecDeque};
+    use std::rc::Rc;
+    use std::sync::{Mutex, PoisonError};
+
+    /// A poll operation in flight on the simulated driver.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub(crate) struct SimPoll {
+        pub helper: HANDLE,
+        pub base_socket: HANDLE,
+        pub afd_events: u32,
+    }
+
+    /// Everything the simulated driver did, shared with the test so that it
+    /// can still be inspected once the port has been dropped.
+    #[derive(Debug, Default)]
+    pub(crate) struct SimState {
+        pub open_helpers: HashSet<HANDLE>,
+        pub helpers_opened: usize,
+        pub helpers_closed: usize,
+        pub polls: HashMap<usize, SimPoll>,
+        pub polls_started: usize,
+        pub polls_cancelled: usize,
+        /// How many of the next poll cancellations fail.
+        pub failing_cancels: usize,
+        /// Zero byte reads in flight, by id, on these sockets.
+        pub zero_reads: HashMap<usize, HANDLE>,
+        // The ids ever probed with zero byte reads.
+        zero_read_ids: HashSet<usize>,
+        pub completions: VecDeque<SimStatus>,
+        pub closed_sockets: HashSet<HANDLE>,
+        /// Sockets whose completions go to another port.
+        pub foreign_sockets: HashSet<HANDLE>,
+        pub port_closed: usize,
+        pub associated: HashSet<HANDLE>,
+        /// Reads and writes in flight, by the id they complete with.
+        pub ios: HashMap<usize, (HANDLE, IoRequest)>,
+        pub ios_cancelled: usize,
+        // Results of the reads and writes completed by the test.
+        io_results: HashMap<usize, io::Result<usize>>,
+        next_io: usize,
+        /// User events posted from any thread, queued as completions by the
+        /// next `wait`.
+        pub posted: Arc<SimPoster>,
+        pub clock: SimClock,
+        next_helper: HANDLE,
+    }
+
+    impl SimState {
+        /// Completes every poll on `base_socket` interested in any of
+        /// `afd_events`, like the AFD driver does when the socket state
+        /// changes.
+        pub(crate) fn signal(&mut self, base_socket: HANDLE, afd_events: u32) {
+            let mut ids: Vec<usize> = self
+                .polls
+                .iter()
+                .filter(|(_, poll)| {
+                    poll.base_socket == base_socket && poll.afd_events & afd_events != 0
+                })
+                .map(|(&id, _)| id)
+                .collect();
+            ids.sort();
+
+            for id in ids {
+                let poll = self.polls.remove(&id).unwrap();
+                let result = PollResult::Events(poll.afd_events & afd_events);
+                self.completions.push_back(SimStatus::poll(id, result));
+            }
+
+            let read_events = afd_events & ZERO_BYTE_READ_EVENTS;
+            let mut ids: Vec<usize> = self
+                .zero_reads
+                .iter()
+                .filter(|&(_, &socket)| socket == base_socket && read_events != 0)
+                .map(|(&id, _)| id)
+                .collect();
+            ids.sort();
+
+            for id in ids {
+                self.zero_reads.remove(&id);
+                let result = PollResult::Events(read_events);
+                self.completions.push_back(SimStatus::zero_read(id, result));
+            }
+        }
+
+        /// Closes `base_socket`, pending polls report `AFD_POLL_LOCAL_CLOSE`
+        /// and new ones fail.
+        pub(crate) fn close_socket(&mut self, base_socket: HANDLE) {
+            self.closed_sockets.insert(base_socket);
+            self.signal(base_socket, AFD_POLL_LOCAL_CLOSE);
+        }
+
+        /// Completes the read or write `id` with `result`.
+        pub(crate) fn complete_io(&mut self, id: usize, result: io::Result<usize>) {
+            assert!(self.ios.contains_key(&id), "no operation {} in flight", id);
+            self.io_results.insert(id, result);
+            self.completions
+                .push_back(SimStatus::raw(Key::Io.raw(), id));
+        }
+
+        /// Returns the poll in flight for `base_socket`, if any.
+        pub(crate) fn poll_of(&self, base_socket: HANDLE) -> Option<SimPoll> {
+            self.polls
+                .values()
+                .find(|poll| poll.base_socket == base_socket)
+                .cloned()
+        }
+    }
+
+    /// The time of the simulated driver, which only passes while it waits
+    /// without completions.
+    #[derive(Debug)]
+    pub(crate) struct SimClock {
+        pub start: Instant,
+        pub elapsed: Duration,
+        /// How long the next waits last before they return empty handed,
+        /// the whole timeout once this is empty.
+        pub early_returns: VecDeque<Duration>,
+        /// The timeouts of every wait so far.
+        pub waits: Vec<Option<Duration>>,
+    }
+
+    impl Default for SimClock {
+        fn default() -> SimClock {
+            SimClock {
+                start: Instant::now(),
+                elapsed: Duration::ZERO,
+                early_returns: VecDeque::new(),
+                waits: Vec::new(),
+            }
+        }
+    }
+
+    impl SimClock {
+        pub(crate) fn now(&self) -> Instant {
+            self.start + self.elapsed
+        }
+
+        /// Records a wait with `timeout`, which lasts as `early_returns` say
+        /// if there is nothing to dequeue and no time at all otherwise.
+        pub(crate) fn wait(&mut self, timeout: Option<Duration>, idle: bool) {
+            self.waits.push(timeout);
+            if idle {
+                let timeout = timeout.expect("waiting forever without completions");
+                self.elapsed += match self.early_returns.pop_front() {
+                    Some(early) => early.min(timeout),
+                    None => timeout,
+                };
+            }
+        }
+    }
+
+    #[derive(Debug, Default)]
+    pub(crate) struct SimPoster(Mutex<VecDeque<usize>>);
+
+    impl SimPoster {
+        /// Returns the number of user events posted and not yet dequeued.
+        pub(crate) fn len(&self) -> usize {
+            self.0.lock().unwrap_or_else(PoisonError::into_inner).len()
+        }
+    }
+
+    impl Post for SimPoster {
+        fn post(&self, id: usize) -> io::Result<()> {
+            self.0
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push_back(id);
+            Ok(())
+        }
+    }
+
+    /// A completion packet as the port dequeues it.
+    #[derive(Debug, Clone)]
+    pub(crate) struct SimStatus {
+        pub key: usize,
+        /// What the OVERLAPPED of the packet would be.
+        pub value: usize,
+        // Where an AFD packet points to.
+        result: Option<PollResult>,
+    }
+
+    impl SimStatus {
+        pub(crate) fn poll(id: usize, result: PollResult) -> SimStatus {
+            SimStatus::with_result(Key::Afd, id, result)
+        }
+
+        pub(crate) fn zero_read(id: usize, result: PollResult) -> SimStatus {
+            SimStatus::with_result(Key::ZeroRead, id, result)
+        }
+
+        fn with_result(key: Key, id: usize, result: PollResult) -> SimStatus {
+            SimStatus {
+                key: key.raw(),
+                value: id,
+                result: Some(result),
+            }
+        }
+
+        pub(crate) fn raw(key: usize, value: usize) -> SimStatus {
+            SimStatus {
+                key,
+                value,
+                result: None,
+            }
+        }
+    }
+
+    impl RawEvent for SimStatus {
+        fn zeroed() -> SimStatus {
+            SimStatus::raw(0, 0)
+        }
+    }
+
+    /// A driver which only keeps books, operations complete when the test
+    /// signals them through the shared `SimState`.
+    pub(crate) struct SimDriver(Rc<RefCell<SimState>>);
+
+    impl SimDriver {
+        pub(crate) fn new() -> (SimDriver, Rc<RefCell<SimState>>) {
+            let state = Rc::new(RefCell::new(SimState::default()));
+            (SimDriver(state.clone()), state)
+        }
+    }
+
+    impl Driver for SimDriver {
+        type Status = SimStatus;
+
+        fn open_helper(&mut self) -> io::Result<HANDLE> {
+            let mut state = self.0.borrow_mut();
+            state.next_helper += 4;
+            let helper = state.next_helper;
+            state.open_helpers.insert(helper);
+            state.helpers_opened += 1;
+            Ok(helper)
+        }
+
+        fn close_helper(&mut self, helper: HANDLE) {
+            let mut state = self.0.borrow_mut();
+            assert!(
+                state.open_helpers.remove(&helper),
+                "helper {} closed twice",
+                helper
+            );
+            assert!(
+                state.polls.values().all(|poll| poll.helper != helper),
+                "helper {} closed with a poll in flight",
+                helper
+            );
+            state.helpers_closed += 1;
+        }
+
+        fn start_poll(
+            &mut self,
+            helper: HANDLE,
+            base_socket: HANDLE,
+            afd_events: u32,
+            id: usize,
+        ) -> io::Result<PollStart> {
+            let mut state = self.0.borrow_mut();
+            assert!(state.open_helpers.contains(&helper));
+            if state.closed_sockets.contains(&base_socket) {
+                return Ok(PollStart::SocketClosed);
+            }
+
+            let poll = SimPoll {
+                helper,
+                base_socket,
+                afd_events,
+            };
+            assert!(
+                state.polls.insert(id, poll).is_none(),
+                "second poll started for socket {}",
+                id
+            );
+            state.polls_started += 1;
+            Ok(PollStart::Pending)
+        }
+
+        fn cancel_poll(&mut self, helper: HANDLE, id: usize) -> io::Result<()> {
+            let mut state = self.0.borrow_mut();
+            if state.failing_cancels > 0 {
+                state.failing_cancels -= 1;
+                return Err(io::Error::from(io::ErrorKind::PermissionDenied));
+            }
+            // A poll which completed already is not in the map anymore, its
+            // completion is queued and there is nothing to cancel.
+            let poll = match state.polls.remove(&id) {
+                Some(poll) => poll,
+                None => return Err(io::Error::from_raw_os_error(ERROR_NOT_FOUND)),
+            };
+            assert_eq!(poll.helper, helper);
+            state.polls_cancelled += 1;
+            let status = SimStatus::poll(id, PollResult::Cancelled);
+            state.completions.push_back(status);
+            Ok(())
+        }
+
+        fn prepare_zero_read(&mut self, socket: HANDLE) -> io::Result<()> {
+            match self.0.borrow().foreign_sockets.contains(&socket) {
+                true => Err(io::Error::from(io::ErrorKind::InvalidInput)),
+                false => Ok(()),
+            }
+        }
+
+        fn start_zero_read(&mut self, socket: HANDLE, id: usize) -> io::Result<PollStart> {
+            let mut state = self.0.borrow_mut();
+            if state.closed_sockets.contains(&socket) {
+                return Ok(PollStart::SocketClosed);
+            }
+            assert!(
+                state.zero_reads.insert(id, socket).is_none(),
+                "second zero byte read started for socket {}",
+                id
+            );
+            state.zero_read_ids.insert(id);
+            Ok(PollStart::Pending)
+        }
+
+        fn cancel_zero_read(&mut self, socket: HANDLE, id: usize) -> io::Result<()> {
+            let mut state = self.0.borrow_mut();
+            let read_socket = match state.zero_reads.remove(&id) {
+                Some(read_socket) => read_socket,
+                None => return Err(io::Error::from_raw_os_error(ERROR_NOT_FOUND)),
+            };
+            assert_eq!(read_socket, socket);
+            let status = SimStatus::zero_read(id, PollResult::Cancelled);
+            state.completions.push_back(status);
+            Ok(())
+        }
+
+        fn now(&self) -> Instant {
+            self.0.borrow().clock.now()
+        }
+
+        fn wait(
+            &mut self,
+            statuses: &mut [SimStatus],
+            timeout: Option<Duration>,
+        ) -> io::Result<usize> {
+            let mut state = self.0.borrow_mut();
+            let posted: Vec<usize> = state
+                .posted
+                .0
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .drain(..)
+                .collect();
+            state.completions.extend(
+                posted
+                    .into_iter()
+                    .map(|id| SimStatus::raw(Key::User.raw(), id)),
+            );
+            let idle = state.completions.is_empty();
+            state.clock.wait(timeout, idle);
+
+            let mut n = 0;
+            while n < statuses.len() {
+                match state.completions.pop_front() {
+                    Some(status) => statuses[n] = status,
+                    None => break,
+                }
+                n += 1;
+            }
+            Ok(n)
+        }
+
+        fn completion(&mut self, status: &SimStatus) -> Option<Completion> {
+            match Key::from_raw(status.key) {
+                // Like the completion port, either key only finds operations
+                // of its own kind.
+                key @ Key::Afd | key @ Key::ZeroRead => {
+                    let zero_read = self.0.borrow().zero_read_ids.contains(&status.value);
+                    if zero_read != (key == Key::ZeroRead) {
+                        return None;
+                    }
+                    status.result.map(|result| Completion::Poll {
+                        id: status.value,
+                        result,
+                    })
+                }
+                Key::Waker => Some(Completion::Wake),
+                Key::User => Some(Completion::User { id: status.value }),
+                Key::Io => {
+                    let mut state = self.0.borrow_mut();
+                    let result = state.io_results.remove(&status.value)?;
+                    let (_, request) = state.ios.remove(&status.value).unwrap();
+                    Some(Completion::Io(request.complete(result)))
+                }
+                Key::Foreign(_) => None,
+            }
+        }
+
+        fn poster(&self) -> Arc<dyn Post> {
+            self.0.borrow().posted.clone()
+        }
+
+        fn associate(&mut self, handle: HANDLE) -> io::Result<()> {
+            self.0.borrow_mut().associated.insert(handle);
+            Ok(())
+        }
+
+        fn submit(&mut self, handle: HANDLE, request: IoRequest) -> io::Result<()> {
+            let mut state = self.0.borrow_mut();
+            assert!(state.associated.contains(&handle));
+            state.next_io += 1;
+            let id = state.next_io;
+            state.ios.insert(id, (handle, request));
+            Ok(())
+        }
+
+        fn cancel_ios(&mut self) -> io::Result<()> {
+            let mut state = self.0.borrow_mut();
+            // Operations which completed already have their result.
+            let mut ids: Vec<usize> = state
+                .ios
+                .keys()
+                .filter(|id| !state.io_results.contains_key(id))
+                .cloned()
+                .collect();
+            ids.sort();
+            for id in ids {
+                state.ios_cancelled += 1;
+                let cancelled = io::Error::from(io::ErrorKind::Interrupted);
+                state.complete_io(id, Err(cancelled));
+            }
+            Ok(())
+        }
+
+        fn ios_in_flight(&self) -> bool {
+            !self.0.borrow().ios.is_empty()
+        }
+    }
+
+    impl Drop for SimDriver {
+        fn drop(&mut self) {
+            self.0.borrow_mut().port_closed += 1;
+        }
+    }
+}
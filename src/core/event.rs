@@ -0,0 +1,332 @@
+use crate::core::proactor::IoCompletion;
+use crate::core::token::Token;
+use std::collections::VecDeque;
+use std::{slice, vec};
+
+use crate::core::ready::Ready;
+
+/// A readiness event reported by `Selector::select`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    token: Token,
+    readiness: Ready,
+    epoll_events: Option<u32>,
+    afd_events: Option<u32>,
+}
+
+impl Event {
+    /// Creates an event, mainly useful to test code consuming events.
+    ///
+    /// Events created this way carry no raw masks.
+    pub fn new(readiness: Ready, token: Token) -> Event {
+        Event {
+            token,
+            readiness,
+            epoll_events: None,
+            afd_events: None,
+        }
+    }
+
+    /// Creates an event from the masks reported by the backend, the
+    /// readiness is derived from the epoll mask.
+    pub(crate) fn from_raw(token: Token, epoll_events: u32, afd_events: Option<u32>) -> Event {
+        Event {
+            token,
+            readiness: Ready::from_epoll(epoll_events),
+            epoll_events: Some(epoll_events),
+            afd_events,
+        }
+    }
+
+    /// Returns the token the event source was registered with.
+    pub fn token(&self) -> Token {
+        self.token
+    }
+
+    /// Returns the full readiness set of the event.
+    pub fn readiness(&self) -> Ready {
+        self.readiness
+    }
+
+    pub fn is_readable(&self) -> bool {
+        self.readiness.is_readable()
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.readiness.is_writable()
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.readiness.is_error()
+    }
+
+    pub fn is_read_closed(&self) -> bool {
+        self.readiness.is_read_closed()
+    }
+
+    pub fn is_write_closed(&self) -> bool {
+        self.readiness.is_write_closed()
+    }
+
+    pub fn is_priority(&self) -> bool {
+        self.readiness.is_priority()
+    }
+
+    pub fn is_connection_reset(&self) -> bool {
+        self.readiness.is_connection_reset()
+    }
+
+    pub fn is_closed_locally(&self) -> bool {
+        self.readiness.is_closed_locally()
+    }
+
+    pub fn is_aio(&self) -> bool {
+        self.readiness.is_aio()
+    }
+
+    pub fn is_lio(&self) -> bool {
+        self.readiness.is_lio()
+    }
+
+    /// Returns the epoll mask the event was derived from. On Windows this is
+    /// the AFD mask translated to epoll and filtered by the registered
+    /// interests.
+    pub fn raw_epoll_events(&self) -> Option<u32> {
+        self.epoll_events
+    }
+
+    /// Returns the mask reported by the AFD driver, only present on Windows
+    /// and only if the poll operation completed successfully.
+    pub fn raw_afd_events(&self) -> Option<u32> {
+        self.afd_events
+    }
+}
+
+/// A raw completion record as filled in by the operating system.
+///
+/// `Events` keeps a buffer of these around so the backend does not have to
+/// allocate on every call to `select`.
+pub trait RawEvent: Clone {
+    /// Returns a record suitable for initializing the buffer.
+    fn zeroed() -> Self;
+}
+
+/// A buffer of events filled in by `Selector::select`.
+///
+/// A call to `select` reports at most `capacity()` events and I/O
+/// completions together. Completions which
+/// do not fit are left queued in the operating system, and events which the
+/// backend produced beyond the capacity are held back, both are reported by
+/// the next call to `select`.
+#[derive(Debug)]
+pub struct Events<S> {
+    /// Raw I/O event completions are filled in here by the backend. These
+    /// are then processed to figure out which events should be reported.
+    statuses: Box<[S]>,
+
+    /// Literal events returned by `get` to the upwards `EventLoop`.
+    events: Vec<Event>,
+
+    /// Events which did not fit into `events`, they are moved over when the
+    /// next `select` starts.
+    overflow: VecDeque<Event>,
+
+    /// Reads and writes which completed, taken from the `statuses` like the
+    /// events.
+    completions: Vec<IoCompletion>,
+}
+
+impl<S: RawEvent> Events<S> {
+    /// Creates a buffer for up to `cap` events per call to `select`. A
+    /// buffer without capacity never reports any event.
+    pub fn with_capacity(cap: usize) -> Events<S> {
+        Events {
+            statuses: vec![S::zeroed(); cap].into_boxed_slice(),
+            events: Vec::with_capacity(cap),
+            overflow: VecDeque::new(),
+            completions: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    /// Returns the maximum number of events reported by one call to
+    /// `select`.
+    pub fn capacity(&self) -> usize {
+        self.statuses.len()
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&Event> {
+        self.events.get(idx)
+    }
+
+    /// Returns an iterator over the events.
+    pub fn iter(&self) -> slice::Iter<'_, Event> {
+        self.events.iter()
+    }
+
+    /// Removes all events and returns them by value.
+    pub fn drain(&mut self) -> vec::Drain<'_, Event> {
+        self.events.drain(..)
+    }
+
+    /// Adds an event, it is held back for the next `select` if the buffer
+    /// is full.
+    pub fn push_event(&mut self, event: Event) {
+        if self.events.len() < self.capacity() {
+            self.events.push(event);
+        } else {
+            self.overflow.push_back(event);
+        }
+    }
+
+    /// Returns an iterator over the I/O completions.
+    pub fn completions(&self) -> slice::Iter<'_, IoCompletion> {
+        self.completions.iter()
+    }
+
+    /// Removes all I/O completions and returns them by value, handing the
+    /// buffers back.
+    pub fn drain_completions(&mut self) -> vec::Drain<'_, IoCompletion> {
+        self.completions.drain(..)
+    }
+
+    pub(crate) fn push_completion(&mut self, completion: IoCompletion) {
+        self.completions.push(completion);
+    }
+
+    /// Removes all events and I/O completions. Events held back for the next
+    /// `select` are kept.
+    pub fn clear(&mut self) {
+        self.events.clear();
+        self.completions.clear();
+    }
+
+    /// Prepares the buffer for a `select`, events held back by the previous
+    /// one are reported first.
+    pub(crate) fn reset(&mut self) {
+        self.events.clear();
+        self.completions.clear();
+        while self.events.len() < self.capacity() {
+            match self.overflow.pop_front() {
+                Some(event) => self.events.push(event),
+                None => break,
+            }
+        }
+    }
+
+    pub(crate) fn statuses(&self) -> &[S] {
+        &self.statuses
+    }
+
+    /// Returns the part of the completion buffer which still fits into the
+    /// events, the backend must not dequeue more completions than that.
+    pub(crate) fn statuses_mut(&mut self) -> &mut [S] {
+        let room = self.capacity() - self.events.len() - self.completions.len();
+        &mut self.statuses[..room]
+    }
+}
+
+impl<'a, S: RawEvent> IntoIterator for &'a Events<S> {
+    type Item = &'a Event;
+    type IntoIter = slice::Iter<'a, Event>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::translate::{EPOLLIN, EPOLLRDHUP};
+
+    #[test]
+    fn accessors_follow_readiness() {
+        let event = Event::new(Ready::READABLE | Ready::READ_CLOSED, Token(3));
+
+        assert_eq!(event.token(), Token(3));
+        assert!(event.is_readable());
+        assert!(event.is_read_closed());
+        assert!(!event.is_writable());
+        assert!(!event.is_write_closed());
+        assert!(!event.is_error());
+        assert!(!event.is_priority());
+        assert_eq!(event.raw_epoll_events(), None);
+        assert_eq!(event.raw_afd_events(), None);
+    }
+
+    #[test]
+    fn raw_masks_are_kept() {
+        let event = Event::from_raw(Token(1), EPOLLIN | EPOLLRDHUP, Some(0x0008));
+
+        assert_eq!(event.readiness(), Ready::READABLE | Ready::READ_CLOSED);
+        assert_eq!(event.raw_epoll_events(), Some(EPOLLIN | EPOLLRDHUP));
+        assert_eq!(event.raw_afd_events(), Some(0x0008));
+        assert_ne!(event, Event::new(event.readiness(), Token(1)));
+        assert_eq!(event.clone(), event);
+    }
+
+    impl RawEvent for u64 {
+        fn zeroed() -> u64 {
+            0
+        }
+    }
+
+    fn event(token: usize) -> Event {
+        Event::new(Ready::READABLE, Token(token))
+    }
+
+    #[test]
+    fn iterates_and_drains() {
+        let mut events = Events::<u64>::with_capacity(4);
+        assert_eq!(events.capacity(), 4);
+        events.push_event(event(1));
+        events.push_event(event(2));
+
+        let tokens: Vec<Token> = events.iter().map(Event::token).collect();
+        assert_eq!(tokens, [Token(1), Token(2)]);
+        assert_eq!((&events).into_iter().count(), 2);
+
+        let drained: Vec<Event> = events.drain().collect();
+        assert_eq!(drained, [event(1), event(2)]);
+        assert!(events.is_empty());
+        assert_eq!(events.statuses_mut().len(), 4);
+    }
+
+    #[test]
+    fn events_beyond_capacity_are_not_lost() {
+        let mut events = Events::<u64>::with_capacity(2);
+        for token in 0..5 {
+            events.push_event(event(token));
+        }
+        assert_eq!(events.len(), 2);
+        assert!(events.statuses_mut().is_empty());
+
+        let mut seen = Vec::new();
+        while !events.is_empty() {
+            assert!(events.len() <= events.capacity());
+            seen.extend(events.drain().map(|e| e.token()));
+            events.reset();
+        }
+        assert_eq!(seen, (0..5).map(Token).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn held_back_events_limit_new_completions() {
+        let mut events = Events::<u64>::with_capacity(3);
+        for token in 0..4 {
+            events.push_event(event(token));
+        }
+
+        events.reset();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events.statuses_mut().len(), 2);
+    }
+}
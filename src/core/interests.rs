@@ -0,0 +1,442 @@
+use std::convert::TryFrom;
+use std::str::FromStr;
+use std::{error, fmt, ops};
+
+/// Interests used in registering.
+///
+/// Interests are used in [registering] [`event::Source`]s with [`Poll`], they
+/// indicate what readiness should be monitored for. For example if a socket is
+/// registered with [readable] interests and the socket becomes writable, no
+/// event will be returned from a call to [`poll`].
+///
+/// The textual form used by `Display` and `FromStr` joins the names of the
+/// single interests with `|`, e.g. `READABLE|WRITABLE`, and is `EMPTY` for
+/// the empty set.
+#[derive(Copy, PartialEq, Eq, Clone, PartialOrd, Ord)]
+#[repr(transparent)]
+pub struct Interests(u8);
+
+// These must be unique.
+const READABLE: u8 = 0b0_001;
+const WRITABLE: u8 = 0b0_010;
+// The following are not available on all platforms.
+#[cfg_attr(
+    not(any(
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "ios",
+        target_os = "macos"
+    )),
+    allow(dead_code)
+)]
+const AIO: u8 = 0b0_100;
+#[cfg_attr(not(target_os = "freebsd"), allow(dead_code))]
+const LIO: u8 = 0b1_000;
+const PRIORITY: u8 = 0b10_000;
+const CONNECTION_RESET: u8 = 0b100_000;
+const CLOSED_LOCALLY: u8 = 0b1_000_000;
+
+/// Every interest available on this platform, with its textual name, in
+/// iteration order.
+const NAMES: &[(u8, &str)] = &[
+    (READABLE, "READABLE"),
+    (WRITABLE, "WRITABLE"),
+    #[cfg(any(
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "ios",
+        target_os = "macos"
+    ))]
+    (AIO, "AIO"),
+    #[cfg(target_os = "freebsd")]
+    (LIO, "LIO"),
+    (PRIORITY, "PRIORITY"),
+    (CONNECTION_RESET, "CONNECTION_RESET"),
+    (CLOSED_LOCALLY, "CLOSED_LOCALLY"),
+];
+
+const fn all() -> u8 {
+    let mut all = 0;
+    let mut i = 0;
+    while i < NAMES.len() {
+        all |= NAMES[i].0;
+        i += 1;
+    }
+    all
+}
+
+impl Interests {
+    /// Returns an empty `Interests` set.
+    pub const EMPTY: Interests = Interests(0);
+
+    /// Returns a `Interests` set representing readable interests.
+    pub const READABLE: Interests = Interests(READABLE);
+
+    /// Returns a `Interests` set representing writable interests.
+    pub const WRITABLE: Interests = Interests(WRITABLE);
+
+    /// Returns a `Interests` set representing priority interests, used to
+    /// be notified of out-of-band data.
+    pub const PRIORITY: Interests = Interests(PRIORITY);
+
+    /// Returns a `Interests` set representing connection reset interests.
+    ///
+    /// Only the AFD backend tells an aborted connection apart from other
+    /// hang-ups, other backends never report it.
+    pub const CONNECTION_RESET: Interests = Interests(CONNECTION_RESET);
+
+    /// Returns a `Interests` set representing interest in the socket being
+    /// closed behind the selector's back.
+    ///
+    /// Without it the AFD backend silently drops the registration of a
+    /// closed socket. With it a last event tells the owner that the
+    /// registration is gone. Other backends never report it.
+    pub const CLOSED_LOCALLY: Interests = Interests(CLOSED_LOCALLY);
+
+    /// Returns a `Interests` set representing AIO completion interests.
+    #[cfg(any(
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "ios",
+        target_os = "macos"
+    ))]
+    pub const AIO: Interests = Interests(AIO);
+
+    /// Returns a `Interests` set representing LIO completion interests.
+    #[cfg(target_os = "freebsd")]
+    pub const LIO: Interests = Interests(LIO);
+
+    /// Returns a `Interests` set of every interest available on this
+    /// platform.
+    pub const ALL: Interests = Interests(all());
+
+    /// Returns the union of `self` and `other`.
+    pub const fn add(self, other: Interests) -> Interests {
+        Interests(self.0 | other.0)
+    }
+
+    /// Returns `self` without the interests in `other`.
+    pub const fn remove(self, other: Interests) -> Interests {
+        Interests(self.0 & !other.0)
+    }
+
+    /// Returns true if `self` is a superset of `other`.
+    pub const fn contains(self, other: Interests) -> bool {
+        (self.0 & other.0) == other.0
+    }
+
+    /// Returns true if the set is empty.
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns true if the value includes readable readiness.
+    pub const fn is_readable(self) -> bool {
+        (self.0 & READABLE) != 0
+    }
+
+    /// Returns true if the value includes writable readiness.
+    pub const fn is_writable(self) -> bool {
+        (self.0 & WRITABLE) != 0
+    }
+
+    /// Returns true if the value includes priority readiness.
+    pub const fn is_priority(self) -> bool {
+        (self.0 & PRIORITY) != 0
+    }
+
+    /// Returns true if the value includes connection reset interests.
+    pub const fn is_connection_reset(self) -> bool {
+        (self.0 & CONNECTION_RESET) != 0
+    }
+
+    /// Returns true if the value includes closed locally interests.
+    pub const fn is_closed_locally(self) -> bool {
+        (self.0 & CLOSED_LOCALLY) != 0
+    }
+
+    /// Returns true if `Interests` contains AIO readiness
+    pub const fn is_aio(self) -> bool {
+        (self.0 & AIO) != 0
+    }
+
+    /// Returns true if `Interests` contains LIO readiness
+    pub const fn is_lio(self) -> bool {
+        (self.0 & LIO) != 0
+    }
+
+    /// Returns an iterator over the single interests in the set.
+    pub fn iter(self) -> Iter {
+        Iter { remaining: self.0 }
+    }
+}
+
+impl ops::BitOr for Interests {
+    type Output = Self;
+
+    #[inline]
+    fn bitor(self, other: Self) -> Self {
+        self.add(other)
+    }
+}
+
+impl ops::BitOrAssign for Interests {
+    #[inline]
+    fn bitor_assign(&mut self, other: Self) {
+        *self = self.add(other);
+    }
+}
+
+impl ops::BitAnd for Interests {
+    type Output = Self;
+
+    #[inline]
+    fn bitand(self, other: Self) -> Self {
+        Interests(self.0 & other.0)
+    }
+}
+
+impl ops::BitAndAssign for Interests {
+    #[inline]
+    fn bitand_assign(&mut self, other: Self) {
+        *self = *self & other;
+    }
+}
+
+impl ops::Sub for Interests {
+    type Output = Self;
+
+    #[inline]
+    fn sub(self, other: Self) -> Self {
+        self.remove(other)
+    }
+}
+
+impl ops::SubAssign for Interests {
+    #[inline]
+    fn sub_assign(&mut self, other: Self) {
+        *self = self.remove(other);
+    }
+}
+
+/// Iterator over the single interests of an `Interests` set, lowest bit
+/// first.
+#[derive(Debug, Clone)]
+pub struct Iter {
+    remaining: u8,
+}
+
+impl Iterator for Iter {
+    type Item = Interests;
+
+    fn next(&mut self) -> Option<Interests> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let lowest = self.remaining & self.remaining.wrapping_neg();
+        self.remaining &= !lowest;
+        Some(Interests(lowest))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let n = self.remaining.count_ones() as usize;
+        (n, Some(n))
+    }
+}
+
+impl ExactSizeIterator for Iter {}
+
+impl IntoIterator for Interests {
+    type Item = Interests;
+    type IntoIter = Iter;
+
+    fn into_iter(self) -> Iter {
+        self.iter()
+    }
+}
+
+impl From<Interests> for u8 {
+    fn from(interests: Interests) -> u8 {
+        interests.0
+    }
+}
+
+/// Error returned when converting a `u8` with bits which are not interests
+/// on this platform.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidInterests(u8);
+
+impl fmt::Display for InvalidInterests {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid interest bits {:#04x}", self.0)
+    }
+}
+
+impl error::Error for InvalidInterests {}
+
+impl TryFrom<u8> for Interests {
+    type Error = InvalidInterests;
+
+    fn try_from(bits: u8) -> Result<Interests, InvalidInterests> {
+        match bits & !Interests::ALL.0 {
+            0 => Ok(Interests(bits)),
+            unknown => Err(InvalidInterests(unknown)),
+        }
+    }
+}
+
+/// Error returned when parsing an unknown interest name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseInterestsError(String);
+
+impl fmt::Display for ParseInterestsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown interest {:?}", self.0)
+    }
+}
+
+impl error::Error for ParseInterestsError {}
+
+impl FromStr for Interests {
+    type Err = ParseInterestsError;
+
+    fn from_str(s: &str) -> Result<Interests, ParseInterestsError> {
+        if s.trim() == "EMPTY" {
+            return Ok(Interests::EMPTY);
+        }
+
+        let mut interests = Interests::EMPTY;
+        for name in s.split('|').map(str::trim) {
+            match NAMES.iter().find(|&&(_, n)| n == name) {
+                Some(&(bit, _)) => interests |= Interests(bit),
+                None => return Err(ParseInterestsError(name.to_owned())),
+            }
+        }
+        Ok(interests)
+    }
+}
+
+impl Interests {
+    fn write_names(self, fmt: &mut fmt::Formatter<'_>, separator: &str) -> fmt::Result {
+        let mut one = false;
+        for &(bit, name) in NAMES {
+            if self.0 & bit != 0 {
+                if one {
+                    fmt.write_str(separator)?
+                }
+                fmt.write_str(name)?;
+                one = true
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Interests {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return fmt.write_str("EMPTY");
+        }
+        self.write_names(fmt, "|")
+    }
+}
+
+impl fmt::Debug for Interests {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return fmt.write_str("(empty)");
+        }
+        self.write_names(fmt, " | ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_operations() {
+        const RW: Interests = Interests::READABLE.add(Interests::WRITABLE);
+
+        assert!(RW.contains(Interests::READABLE));
+        assert!(RW.contains(RW));
+        assert!(!Interests::READABLE.contains(RW));
+        assert!(RW.contains(Interests::EMPTY));
+        assert_eq!(RW.remove(Interests::WRITABLE), Interests::READABLE);
+        assert_eq!(RW - RW, Interests::EMPTY);
+        assert!((RW - RW).is_empty());
+        assert_eq!(RW & Interests::WRITABLE, Interests::WRITABLE);
+        assert_eq!(Interests::READABLE & Interests::WRITABLE, Interests::EMPTY);
+
+        let mut interests = Interests::EMPTY;
+        interests |= Interests::PRIORITY;
+        interests |= Interests::READABLE;
+        interests -= Interests::PRIORITY;
+        assert_eq!(interests, Interests::READABLE);
+        interests &= Interests::WRITABLE;
+        assert!(interests.is_empty());
+    }
+
+    #[test]
+    fn iterates_single_interests() {
+        let all: Vec<Interests> = Interests::ALL.iter().collect();
+        assert_eq!(all.len(), NAMES.len());
+        for (interest, &(bit, _)) in all.iter().zip(NAMES) {
+            assert_eq!(u8::from(*interest), bit);
+        }
+
+        assert_eq!(Interests::EMPTY.iter().next(), None);
+        assert_eq!(
+            (Interests::WRITABLE | Interests::PRIORITY)
+                .into_iter()
+                .fold(Interests::EMPTY, |acc, i| acc | i),
+            Interests::WRITABLE | Interests::PRIORITY
+        );
+    }
+
+    #[test]
+    fn try_from_u8() {
+        for bits in 0..=u8::MAX {
+            match Interests::try_from(bits) {
+                Ok(interests) => {
+                    assert_eq!(bits & !Interests::ALL.0, 0);
+                    assert_eq!(u8::from(interests), bits);
+                }
+                Err(err) => assert_eq!(err, InvalidInterests(bits & !Interests::ALL.0)),
+            }
+        }
+    }
+
+    #[test]
+    fn text_round_trip() {
+        assert_eq!(Interests::EMPTY.to_string(), "EMPTY");
+        assert_eq!(
+            (Interests::READABLE | Interests::WRITABLE).to_string(),
+            "READABLE|WRITABLE"
+        );
+        assert_eq!(
+            " WRITABLE | READABLE ".parse(),
+            Ok(Interests::READABLE | Interests::WRITABLE)
+        );
+        assert_eq!(
+            "READABLE|BOGUS".parse::<Interests>(),
+            Err(ParseInterestsError("BOGUS".to_owned()))
+        );
+        assert!("".parse::<Interests>().is_err());
+
+        for bits in 0..=Interests::ALL.0 {
+            if let Ok(interests) = Interests::try_from(bits) {
+                assert_eq!(interests.to_string().parse(), Ok(interests));
+            }
+        }
+    }
+
+    #[test]
+    fn debug_lists_interests() {
+        assert_eq!(format!("{:?}", Interests::EMPTY), "(empty)");
+        assert_eq!(
+            format!("{:?}", Interests::READABLE | Interests::PRIORITY),
+            "READABLE | PRIORITY"
+        );
+    }
+}
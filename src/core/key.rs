@@ -0,0 +1,79 @@
+// Completion keys tell the packets dequeued from a completion port apart by
+// their origin. The port reserves the highest keys for itself, every other
+// key belongs to handles the user associated with the port. The OVERLAPPED
+// of such a packet points at memory the port knows nothing about, so it is
+// never dereferenced.
+
+/// Key of the AFD helper handles, their packets point at a poll operation.
+const AFD_KEY: usize = usize::MAX;
+/// Key of wakeups, their packets carry nothing.
+const WAKER_KEY: usize = usize::MAX - 1;
+/// Key of user events, their packets carry the id of the event in place of
+/// an OVERLAPPED.
+const USER_KEY: usize = usize::MAX - 2;
+/// Key of handles associated for completion based I/O, their packets point
+/// at a read or write operation.
+const IO_KEY: usize = usize::MAX - 3;
+/// Key of sockets probed with zero byte reads, their packets point at a
+/// read operation.
+const ZERO_READ_KEY: usize = usize::MAX - 4;
+
+/// The origin of a completion packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Key {
+    Afd,
+    Waker,
+    User,
+    Io,
+    ZeroRead,
+    /// A handle associated with the port by the user, with this key.
+    Foreign(usize),
+}
+
+impl Key {
+    pub(crate) fn from_raw(key: usize) -> Key {
+        match key {
+            AFD_KEY => Key::Afd,
+            WAKER_KEY => Key::Waker,
+            USER_KEY => Key::User,
+            IO_KEY => Key::Io,
+            ZERO_READ_KEY => Key::ZeroRead,
+            key => Key::Foreign(key),
+        }
+    }
+
+    pub(crate) fn raw(self) -> usize {
+        match self {
+            Key::Afd => AFD_KEY,
+            Key::Waker => WAKER_KEY,
+            Key::User => USER_KEY,
+            Key::Io => IO_KEY,
+            Key::ZeroRead => ZERO_READ_KEY,
+            Key::Foreign(key) => key,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_round_trip() {
+        let keys = [
+            Key::Afd,
+            Key::Waker,
+            Key::User,
+            Key::Io,
+            Key::ZeroRead,
+            Key::Foreign(0),
+        ];
+        for &key in keys.iter() {
+            assert_eq!(Key::from_raw(key.raw()), key);
+        }
+        // Keys a user would pick are never taken for ours.
+        for raw in 0..1024 {
+            assert_eq!(Key::from_raw(raw), Key::Foreign(raw));
+        }
+    }
+}
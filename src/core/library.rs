@@ -0,0 +1,156 @@
+// Reference counted setup of a system library which has to be initialized
+// before its first use and cleaned up after its last one, which is what
+// Winsock wants with `WSAStartup` and `WSACleanup`. Every selector holds a
+// reference, so its hot paths need no check at all.
+
+use std::io;
+use std::sync::{Mutex, PoisonError};
+
+/// Setup and teardown of a library.
+pub(crate) trait Init {
+    fn startup(&self) -> io::Result<()>;
+
+    fn cleanup(&self);
+}
+
+/// A library initialized while at least one `LibraryRef` is alive.
+pub(crate) struct Library<I> {
+    refs: Mutex<usize>,
+    init: I,
+}
+
+impl<I: Init> Library<I> {
+    pub(crate) const fn new(init: I) -> Library<I> {
+        Library {
+            refs: Mutex::new(0),
+            init,
+        }
+    }
+
+    /// Takes a reference, initializing the library if it is the first.
+    pub(crate) fn acquire(&self) -> io::Result<LibraryRef<'_, I>> {
+        // The count is only changed once startup and cleanup have returned,
+        // so it is still right if one of them panicked.
+        let mut refs = self.refs.lock().unwrap_or_else(PoisonError::into_inner);
+        if *refs == 0 {
+            self.init.startup()?;
+        }
+        *refs += 1;
+        Ok(LibraryRef { library: self })
+    }
+
+    #[cfg(test)]
+    fn refs(&self) -> usize {
+        *self.refs.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A reference to an initialized library, the last one to be dropped cleans
+/// the library up.
+pub(crate) struct LibraryRef<'a, I: Init> {
+    library: &'a Library<I>,
+}
+
+impl<I: Init> Drop for LibraryRef<'_, I> {
+    fn drop(&mut self) {
+        let mut refs = self
+            .library
+            .refs
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if *refs == 1 {
+            self.library.init.cleanup();
+        }
+        *refs -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+    use std::panic::{self, AssertUnwindSafe};
+
+    /// Records the calls a Winsock stand-in receives.
+    #[derive(Default)]
+    struct MockWinsock {
+        calls: RefCell<Vec<&'static str>>,
+        fail: Cell<bool>,
+        panic: Cell<bool>,
+    }
+
+    impl Init for MockWinsock {
+        fn startup(&self) -> io::Result<()> {
+            if self.panic.get() {
+                panic!("startup panicked");
+            }
+            self.calls.borrow_mut().push("WSAStartup");
+            match self.fail.get() {
+                // WSASYSNOTREADY
+                true => Err(io::Error::from_raw_os_error(10091)),
+                false => Ok(()),
+            }
+        }
+
+        fn cleanup(&self) {
+            self.calls.borrow_mut().push("WSACleanup");
+        }
+    }
+
+    #[test]
+    fn first_reference_starts_and_last_cleans_up() {
+        let library = Library::new(MockWinsock::default());
+
+        let first = library.acquire().unwrap();
+        let second = library.acquire().unwrap();
+        assert_eq!(*library.init.calls.borrow(), ["WSAStartup"]);
+        drop(first);
+        assert_eq!(*library.init.calls.borrow(), ["WSAStartup"]);
+        drop(second);
+        assert_eq!(*library.init.calls.borrow(), ["WSAStartup", "WSACleanup"]);
+
+        drop(library.acquire().unwrap());
+        assert_eq!(
+            *library.init.calls.borrow(),
+            ["WSAStartup", "WSACleanup", "WSAStartup", "WSACleanup"]
+        );
+        assert_eq!(library.refs(), 0);
+    }
+
+    #[test]
+    fn failed_startup_takes_no_reference() {
+        let library = Library::new(MockWinsock::default());
+
+        library.init.fail.set(true);
+        assert_eq!(
+            library.acquire().err().and_then(|e| e.raw_os_error()),
+            Some(10091)
+        );
+        assert_eq!(library.refs(), 0);
+
+        // Nothing is cleaned up which was never started, the next reference
+        // tries again.
+        library.init.fail.set(false);
+        drop(library.acquire().unwrap());
+        assert_eq!(
+            *library.init.calls.borrow(),
+            ["WSAStartup", "WSAStartup", "WSACleanup"]
+        );
+    }
+
+    #[test]
+    fn poisoned_lock_is_recovered() {
+        let library = Library::new(MockWinsock::default());
+
+        library.init.panic.set(true);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| library.acquire()));
+        assert!(result.is_err());
+        assert!(library.refs.is_poisoned());
+
+        library.init.panic.set(false);
+        let reference = library.acquire().unwrap();
+        assert_eq!(library.refs(), 1);
+        drop(reference);
+        assert_eq!(*library.init.calls.borrow(), ["WSAStartup", "WSACleanup"]);
+    }
+}
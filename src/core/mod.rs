@@ -0,0 +1,35 @@
+// Platform independent part of the crate. Nothing in here may touch the
+// operating system, the backends in `sys` are layered on top of it. What
+// only the AFD backend runs on is built for Windows, and for the tests,
+// which drive it with a simulated driver on any host.
+
+pub(crate) mod afd;
+pub mod backend;
+#[cfg(any(windows, test))]
+pub(crate) mod base_socket;
+pub(crate) mod buffer_pool;
+pub mod builder;
+#[cfg(any(windows, test))]
+pub(crate) mod driver;
+pub mod event;
+pub mod interests;
+#[cfg(any(windows, test))]
+pub(crate) mod key;
+#[cfg(any(windows, test))]
+pub(crate) mod library;
+#[cfg(any(windows, test))]
+pub(crate) mod poll_once;
+#[cfg(any(windows, test))]
+pub(crate) mod poll_state;
+#[cfg(any(windows, test))]
+pub(crate) mod port;
+pub(crate) mod proactor;
+pub mod ready;
+#[cfg(any(windows, test))]
+pub(crate) mod sock;
+#[cfg(any(windows, test))]
+pub mod strategy;
+pub mod token;
+pub(crate) mod translate;
+#[cfg(any(windows, test))]
+pub(crate) mod user_event;
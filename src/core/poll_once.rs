@@ -0,0 +1,118 @@
+// A single poll of a set of sockets, without a selector, with the semantics
+// of `WSAPoll`: errors and hang-ups are reported whatever the interests,
+// and a socket which is no socket anymore is reported rather than failing
+// the whole call. Unlike `WSAPoll`, a connect which failed is reported as
+// an error together with writability, the way `poll(2)` reports it.
+
+use crate::core::interests::Interests;
+use crate::core::ready::Ready;
+use crate::core::translate::{
+    interests_to_epoll, sock_afd_events_to_epoll_events, sock_epoll_events_to_afd_events,
+    AFD_POLL_ABORT, AFD_POLL_LOCAL_CLOSE, EPOLLERR, EPOLLHUP, EPOLLRDHUP, EPOLL_CLOSED_LOCALLY,
+    EPOLL_RESET,
+};
+use std::time::Duration;
+
+/// Returns the AFD events to poll a socket for to observe `interests`.
+pub(crate) fn afd_events(interests: Interests) -> u32 {
+    // EPOLLERR arms AFD_POLL_CONNECT_FAIL, EPOLLHUP arms AFD_POLL_ABORT.
+    sock_epoll_events_to_afd_events(interests_to_epoll(interests) | EPOLLERR | EPOLLHUP)
+}
+
+/// Returns the readiness `afd_events` stand for to a socket polled for
+/// `interests`.
+pub(crate) fn afd_readiness(afd_events: u32, interests: Interests) -> Ready {
+    let wanted = interests_to_epoll(interests);
+    if afd_events & AFD_POLL_LOCAL_CLOSE != 0 {
+        // What `WSAPoll` reports as `POLLNVAL`.
+        return Ready::ERROR | Ready::from_epoll(wanted & EPOLL_CLOSED_LOCALLY);
+    }
+
+    let mut epoll_events = sock_afd_events_to_epoll_events(afd_events);
+    if afd_events & AFD_POLL_ABORT != 0 {
+        epoll_events |= EPOLL_RESET;
+    }
+    Ready::from_epoll(epoll_events & (wanted | EPOLLERR | EPOLLHUP | EPOLLRDHUP))
+}
+
+/// Converts a timeout to the `Timeout` of an `AFD_POLL_INFO`, which is
+/// relative when negative, in units of 100 nanoseconds. It is rounded up so
+/// the poll never ends early, no timeout waits forever.
+pub(crate) fn afd_timeout(timeout: Option<Duration>) -> i64 {
+    match timeout {
+        Some(timeout) => {
+            let units = timeout.as_nanos().div_ceil(100);
+            -(units.min(i64::MAX as u128) as i64)
+        }
+        None => i64::MAX,
+    }
+}
+
+/// Converts a timeout to the milliseconds `WSAPoll` takes. It is rounded up
+/// so the poll never ends early, no timeout is -1 and waits forever.
+pub(crate) fn millis_timeout(timeout: Option<Duration>) -> i32 {
+    match timeout {
+        Some(timeout) => {
+            let millis = timeout.as_nanos().div_ceil(1_000_000);
+            millis.min(i32::MAX as u128) as i32
+        }
+        None => -1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::translate::{
+        AFD_POLL_CONNECT_FAIL, AFD_POLL_DISCONNECT, AFD_POLL_RECEIVE, AFD_POLL_SEND,
+    };
+
+    #[test]
+    fn failed_connect_is_reported() {
+        let interests = Interests::WRITABLE;
+        assert_ne!(afd_events(interests) & AFD_POLL_CONNECT_FAIL, 0);
+        // Both halves are closed, as `poll(2)` adds `POLLHUP`.
+        assert_eq!(
+            afd_readiness(AFD_POLL_CONNECT_FAIL, interests),
+            Ready::WRITABLE | Ready::ERROR | Ready::READ_CLOSED | Ready::WRITE_CLOSED
+        );
+        // Readers are told as well, which `WSAPoll` does not do either.
+        assert!(afd_readiness(AFD_POLL_CONNECT_FAIL, Interests::READABLE).is_error());
+    }
+
+    #[test]
+    fn readiness_follows_the_interests() {
+        let readable = Interests::READABLE;
+        assert_eq!(afd_events(readable) & AFD_POLL_SEND, 0);
+        assert_eq!(afd_readiness(AFD_POLL_RECEIVE, readable), Ready::READABLE);
+        assert!(afd_readiness(AFD_POLL_SEND, readable).is_empty());
+        assert!(afd_readiness(AFD_POLL_DISCONNECT, readable).is_read_closed());
+
+        // Hang-ups are reported regardless, a reset only when asked for.
+        let abort = afd_readiness(AFD_POLL_ABORT, Interests::WRITABLE);
+        assert!(abort.is_read_closed() && abort.is_write_closed());
+        assert!(!abort.is_connection_reset());
+        let reset = Interests::WRITABLE | Interests::CONNECTION_RESET;
+        assert!(afd_readiness(AFD_POLL_ABORT, reset).is_connection_reset());
+
+        let closed = afd_readiness(AFD_POLL_LOCAL_CLOSE | AFD_POLL_RECEIVE, readable);
+        assert_eq!(closed, Ready::ERROR);
+        let closed_locally = readable | Interests::CLOSED_LOCALLY;
+        assert!(afd_readiness(AFD_POLL_LOCAL_CLOSE, closed_locally).is_closed_locally());
+    }
+
+    #[test]
+    fn timeouts_are_relative_and_rounded_up() {
+        assert_eq!(afd_timeout(None), i64::MAX);
+        assert_eq!(afd_timeout(Some(Duration::from_secs(0))), 0);
+        assert_eq!(afd_timeout(Some(Duration::from_nanos(1))), -1);
+        assert_eq!(afd_timeout(Some(Duration::from_millis(15))), -150_000);
+        assert_eq!(afd_timeout(Some(Duration::MAX)), -i64::MAX);
+
+        assert_eq!(millis_timeout(None), -1);
+        assert_eq!(millis_timeout(Some(Duration::from_secs(0))), 0);
+        assert_eq!(millis_timeout(Some(Duration::from_nanos(1))), 1);
+        assert_eq!(millis_timeout(Some(Duration::from_micros(1500))), 2);
+        assert_eq!(millis_timeout(Some(Duration::MAX)), i32::MAX);
+    }
+}
@@ -0,0 +1,300 @@
+use std::{error, fmt, io};
+
+/// State of the AFD poll operation of a socket.
+///
+/// ```text
+///            Submit              Cancel
+///   Idle ------------> Pending ---------> Cancelled
+///    ^ |                  |                   |
+///    | |                  | Complete          | Complete
+///    | +------------------+-------------------+
+///    |
+///    | Delete (from Idle or Cancelled)
+///    v
+///   Deleting { poll_in_flight } --Complete--> Deleting { poll_in_flight: false }
+/// ```
+///
+/// A pending poll has to be cancelled before the socket can be deleted, and
+/// the memory of a deleted socket may only be released once no poll
+/// operation refers to it anymore.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SockPollState {
+    /// No poll operation is in flight.
+    Idle,
+    /// A poll operation is in flight.
+    Pending,
+    /// The poll operation in flight has been cancelled, its completion has
+    /// not been received yet.
+    Cancelled,
+    /// The socket is being deleted, possibly still waiting for the completion
+    /// of a cancelled poll operation.
+    Deleting { poll_in_flight: bool },
+}
+
+/// The inputs driving a `SockPollState`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PollTransition {
+    /// A poll operation has been submitted to the driver.
+    Submit,
+    /// The poll operation in flight has been cancelled.
+    Cancel,
+    /// The completion of the poll operation in flight has been received.
+    Complete,
+    /// The socket has been deleted.
+    Delete,
+}
+
+/// Error returned when a transition is not allowed in the current state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct InvalidTransition {
+    pub state: SockPollState,
+    pub transition: PollTransition,
+}
+
+impl SockPollState {
+    /// Returns the state reached by applying `transition`.
+    pub(crate) fn next(
+        self,
+        transition: PollTransition,
+    ) -> Result<SockPollState, InvalidTransition> {
+        use self::PollTransition::*;
+        use self::SockPollState::*;
+
+        match (self, transition) {
+            (Idle, Submit) => Ok(Pending),
+            (Pending, Cancel) => Ok(Cancelled),
+            (Pending, Complete) | (Cancelled, Complete) => Ok(Idle),
+            (
+                Deleting {
+                    poll_in_flight: true,
+                },
+                Complete,
+            ) => Ok(Deleting {
+                poll_in_flight: false,
+            }),
+            (Idle, Delete) => Ok(Deleting {
+                poll_in_flight: false,
+            }),
+            (Cancelled, Delete) => Ok(Deleting {
+                poll_in_flight: true,
+            }),
+            (state, transition) => Err(InvalidTransition { state, transition }),
+        }
+    }
+
+    /// Applies `transition` in place, leaving the state untouched on error.
+    pub(crate) fn apply(&mut self, transition: PollTransition) -> Result<(), InvalidTransition> {
+        *self = self.next(transition)?;
+        Ok(())
+    }
+
+    /// Returns true if a poll operation still refers to the socket.
+    pub(crate) fn poll_in_flight(self) -> bool {
+        match self {
+            SockPollState::Pending | SockPollState::Cancelled => true,
+            SockPollState::Deleting { poll_in_flight } => poll_in_flight,
+            SockPollState::Idle => false,
+        }
+    }
+
+    pub(crate) fn is_deleting(self) -> bool {
+        matches!(self, SockPollState::Deleting { .. })
+    }
+}
+
+impl fmt::Display for InvalidTransition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid socket poll transition {:?} in state {:?}",
+            self.transition, self.state
+        )
+    }
+}
+
+impl error::Error for InvalidTransition {}
+
+impl From<InvalidTransition> for io::Error {
+    fn from(err: InvalidTransition) -> io::Error {
+        io::Error::other(err)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::PollTransition::*;
+    use super::SockPollState::*;
+    use super::*;
+
+    /// Small xorshift generator, so that random sequences are reproducible
+    /// from their seed.
+    pub(crate) struct Rng(u64);
+
+    impl Rng {
+        pub(crate) fn new(seed: u64) -> Rng {
+            Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+        }
+
+        pub(crate) fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        pub(crate) fn below(&mut self, n: u64) -> u64 {
+            self.next() % n
+        }
+    }
+
+    const STATES: [SockPollState; 5] = [
+        Idle,
+        Pending,
+        Cancelled,
+        Deleting {
+            poll_in_flight: true,
+        },
+        Deleting {
+            poll_in_flight: false,
+        },
+    ];
+
+    const TRANSITIONS: [PollTransition; 4] = [Submit, Cancel, Complete, Delete];
+
+    /// The representation wepoll uses, a poll status plus a separate
+    /// `delete_pending` flag, with its rules spelled out independently.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Model {
+        status: SockPollState,
+        delete_pending: bool,
+    }
+
+    impl Model {
+        fn legal(&self, transition: PollTransition) -> bool {
+            match transition {
+                Submit => !self.delete_pending && self.status == Idle,
+                Cancel => !self.delete_pending && self.status == Pending,
+                Complete => self.status != Idle,
+                // sock_delete() cancels a pending poll before it marks the
+                // socket as deleted.
+                Delete => !self.delete_pending && self.status != Pending,
+            }
+        }
+
+        fn apply(&mut self, transition: PollTransition) {
+            match transition {
+                Submit => self.status = Pending,
+                Cancel => self.status = Cancelled,
+                Complete => self.status = Idle,
+                Delete => self.delete_pending = true,
+            }
+        }
+
+        fn state(&self) -> SockPollState {
+            match (self.delete_pending, self.status) {
+                (false, status) => status,
+                (true, status) => Deleting {
+                    poll_in_flight: status != Idle,
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn transition_table() {
+        let expected = [
+            (Idle, Submit, Some(Pending)),
+            (
+                Idle,
+                Delete,
+                Some(Deleting {
+                    poll_in_flight: false,
+                }),
+            ),
+            (Pending, Cancel, Some(Cancelled)),
+            (Pending, Complete, Some(Idle)),
+            (Cancelled, Complete, Some(Idle)),
+            (
+                Cancelled,
+                Delete,
+                Some(Deleting {
+                    poll_in_flight: true,
+                }),
+            ),
+            (
+                Deleting {
+                    poll_in_flight: true,
+                },
+                Complete,
+                Some(Deleting {
+                    poll_in_flight: false,
+                }),
+            ),
+        ];
+
+        for &state in STATES.iter() {
+            for &transition in TRANSITIONS.iter() {
+                let allowed = expected
+                    .iter()
+                    .find(|&&(s, t, _)| s == state && t == transition)
+                    .and_then(|&(_, _, next)| next);
+
+                match allowed {
+                    Some(next) => assert_eq!(state.next(transition), Ok(next)),
+                    None => assert_eq!(
+                        state.next(transition),
+                        Err(InvalidTransition { state, transition })
+                    ),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn failed_transition_leaves_state_untouched() {
+        let mut state = Pending;
+        assert!(state.apply(Submit).is_err());
+        assert_eq!(state, Pending);
+        assert!(state.apply(Delete).is_err());
+        assert_eq!(state, Pending);
+    }
+
+    #[test]
+    fn invalid_transition_is_an_io_error() {
+        let err = io::Error::from(Idle.next(Cancel).unwrap_err());
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+    }
+
+    #[test]
+    fn random_sequences_follow_the_model() {
+        for seed in 0..500 {
+            let mut rng = Rng::new(seed);
+            let mut state = Idle;
+            let mut model = Model {
+                status: Idle,
+                delete_pending: false,
+            };
+
+            for _ in 0..64 {
+                let transition = TRANSITIONS[rng.below(TRANSITIONS.len() as u64) as usize];
+                let result = state.apply(transition);
+
+                assert_eq!(
+                    result.is_ok(),
+                    model.legal(transition),
+                    "seed {}: {:?} in {:?}",
+                    seed,
+                    transition,
+                    model
+                );
+                if result.is_ok() {
+                    model.apply(transition);
+                }
+
+                assert_eq!(state, model.state(), "seed {}", seed);
+                assert_eq!(state.poll_in_flight(), model.status != Idle);
+                assert_eq!(state.is_deleting(), model.delete_pending);
+            }
+        }
+    }
+}
@@ -0,0 +1,1416 @@
+// The platform independent part of wepoll's `port_state_t`: the registered
+// sockets, their poll groups and the queue of sockets whose AFD poll has to
+// be brought up to date. Everything touching the operating system goes
+// through a `Driver`.
+
+use crate::core::afd::HANDLE;
+use crate::core::backend::Backend;
+use crate::core::builder::{SelectorBuilder, Trigger};
+use crate::core::driver::{Completion, Driver, PollStart, ERROR_NOT_FOUND};
+use crate::core::event::{Event, Events, RawEvent};
+use crate::core::interests::Interests;
+use crate::core::poll_state::SockPollState;
+use crate::core::proactor::{not_associated, IoBuf, IoHandle, IoKind, IoRequest, SelectorId};
+use crate::core::sock::{Feed, PollResult, SockState, UpdateAction};
+use crate::core::strategy::{check_zero_byte_read, Probe, Strategy};
+use crate::core::token::Token;
+use crate::core::translate::{
+    interests_to_epoll, EPOLLERR, EPOLLHUP, EPOLLONESHOT, EPOLL_CLOSED_LOCALLY,
+};
+use crate::core::user_event::{Shared, UserEvent};
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::mem;
+use std::sync::{Arc, Mutex, PoisonError, Weak};
+use std::time::{Duration, Instant};
+
+/// Completions dequeued at once while a port is shutting down.
+const DRAIN_BATCH: usize = 64;
+
+struct PollGroup {
+    helper: HANDLE,
+    group_size: usize,
+}
+
+struct Sock {
+    state: SockState,
+    // The socket itself for zero byte reads.
+    base_socket: HANDLE,
+    // `None` for sockets probed with zero byte reads.
+    poll_group: Option<usize>,
+}
+
+struct User {
+    token: Token,
+    shared: Arc<Shared>,
+}
+
+pub(crate) struct Port<D: Driver> {
+    driver: D,
+    sockets: HashMap<usize, Sock>,
+    poll_groups: Vec<PollGroup>,
+    update_queue: VecDeque<usize>,
+    user_events: HashMap<usize, User>,
+    //ids of the registrations dropped since the last select
+    dropped: Arc<Mutex<Vec<usize>>>,
+    next_id: usize,
+    //sockets sharing one helper handle at most
+    group_size: usize,
+    //epoll flags added to the events of every registration
+    trigger_events: u32,
+    //stamped on the handles associated with the port
+    id: SelectorId,
+}
+
+/// The registration of a socket with a `Port`. Dropping it deregisters the
+/// socket, the port takes care of it by the next `select`.
+#[derive(Debug)]
+pub(crate) struct Registration {
+    dropped: Option<Weak<Mutex<Vec<usize>>>>,
+    id: usize,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        // The port only needs to hear about it if it is still around, it
+        // cancels every poll when it goes.
+        if let Some(dropped) = self.dropped.take().and_then(|weak| weak.upgrade()) {
+            dropped
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push(self.id);
+        }
+    }
+}
+
+impl<D: Driver> Port<D> {
+    /// Creates a port configured by `builder` for a selector using
+    /// `backend`.
+    pub(crate) fn new(
+        driver: D,
+        builder: &SelectorBuilder,
+        backend: Backend,
+    ) -> io::Result<Port<D>> {
+        builder.validate(backend)?;
+
+        let mut sockets = HashMap::new();
+        sockets
+            .try_reserve(builder.capacity)
+            .map_err(|err| io::Error::new(io::ErrorKind::OutOfMemory, err))?;
+
+        Ok(Port {
+            driver,
+            sockets,
+            poll_groups: Vec::new(),
+            update_queue: VecDeque::new(),
+            user_events: HashMap::new(),
+            dropped: Arc::new(Mutex::new(Vec::new())),
+            next_id: 0,
+            group_size: builder.group_size,
+            trigger_events: match builder.trigger_for(backend) {
+                Trigger::Oneshot => EPOLLONESHOT,
+                // Edge triggering is refused by `validate`.
+                Trigger::Level | Trigger::Edge => 0,
+            },
+            id: SelectorId::new(),
+        })
+    }
+
+    /// Registers `base_socket`, the AFD poll is started by the next
+    /// `select`.
+    pub(crate) fn register(
+        &mut self,
+        base_socket: HANDLE,
+        token: Token,
+        interests: Interests,
+    ) -> io::Result<Registration> {
+        let poll_group = self.acquire_poll_group()?;
+        self.insert(base_socket, Some(poll_group), token, interests)
+    }
+
+    /// Registers `socket` with `strategy`, `base_socket` is the outcome of
+    /// resolving its base socket.
+    pub(crate) fn register_with(
+        &mut self,
+        socket: HANDLE,
+        base_socket: io::Result<HANDLE>,
+        token: Token,
+        interests: Interests,
+        strategy: Strategy,
+    ) -> io::Result<Registration> {
+        match strategy.probe(base_socket, interests)? {
+            Probe::Afd(base_socket) => self.register(base_socket, token, interests),
+            Probe::ZeroByteRead => {
+                self.driver.prepare_zero_read(socket)?;
+                self.insert(socket, None, token, interests)
+            }
+        }
+    }
+
+    fn insert(
+        &mut self,
+        base_socket: HANDLE,
+        poll_group: Option<usize>,
+        token: Token,
+        interests: Interests,
+    ) -> io::Result<Registration> {
+        self.next_id += 1;
+        let id = self.next_id;
+        self.sockets.insert(
+            id,
+            Sock {
+                state: SockState::new(),
+                base_socket,
+                poll_group,
+            },
+        );
+
+        self.set_events(id, token, interests);
+        Ok(Registration {
+            dropped: Some(Arc::downgrade(&self.dropped)),
+            id,
+        })
+    }
+
+    /// Creates a user event reported with `token`. Dropping it deregisters
+    /// it by the next `select`.
+    pub(crate) fn user_event(&mut self, token: Token) -> io::Result<UserEvent> {
+        self.next_id += 1;
+        let id = self.next_id;
+        let shared = Arc::new(Shared::new(id, self.driver.poster()));
+        self.user_events.insert(
+            id,
+            User {
+                token,
+                shared: shared.clone(),
+            },
+        );
+
+        let registration = Registration {
+            dropped: Some(Arc::downgrade(&self.dropped)),
+            id,
+        };
+        Ok(UserEvent::new(shared, registration))
+    }
+
+    /// Associates `handle` for completion based I/O, its completions are
+    /// reported with `token`.
+    pub(crate) fn associate(&mut self, handle: HANDLE, token: Token) -> io::Result<IoHandle> {
+        self.driver.associate(handle)?;
+        Ok(IoHandle::new(handle, token, self.id))
+    }
+
+    /// Starts reading into or writing from `buf` at `offset` of a handle
+    /// associated with this port. If it cannot be started the buffer is
+    /// dropped.
+    pub(crate) fn submit(
+        &mut self,
+        handle: &IoHandle,
+        kind: IoKind,
+        buf: IoBuf,
+        offset: u64,
+    ) -> io::Result<()> {
+        // The completions of another port's handle would never arrive here.
+        if handle.selector != self.id {
+            return Err(not_associated());
+        }
+        let request = IoRequest::new(handle, kind, buf, offset);
+        self.driver.submit(handle.handle, request)
+    }
+
+    /// Changes the token and interests of a registration.
+    pub(crate) fn reregister(
+        &mut self,
+        registration: &Registration,
+        token: Token,
+        interests: Interests,
+    ) -> io::Result<()> {
+        let id = self.registered(registration)?;
+        if self.sockets[&id].poll_group.is_none() {
+            check_zero_byte_read(interests)?;
+        }
+        self.set_events(id, token, interests);
+        Ok(())
+    }
+
+    /// Deregisters a socket right away, dropping `registration` afterwards
+    /// has no effect. Its memory is kept until the cancelled poll operation
+    /// has completed.
+    pub(crate) fn deregister(&mut self, registration: &mut Registration) -> io::Result<()> {
+        let id = self.registered(registration)?;
+        registration.dropped = None;
+        self.delete(id, false)
+    }
+
+    /// Returns the id of a registration with this port which has not been
+    /// deleted yet.
+    fn registered(&self, registration: &Registration) -> io::Result<usize> {
+        let ours = match registration.dropped {
+            Some(ref dropped) => Weak::ptr_eq(dropped, &Arc::downgrade(&self.dropped)),
+            None => false,
+        };
+
+        match self.sockets.get(&registration.id) {
+            Some(sock) if ours && !sock.state.is_deleting() => Ok(registration.id),
+            _ => Err(not_registered()),
+        }
+    }
+
+    /// Deletes the sockets whose registration has been dropped. Those which
+    /// fail to be deleted are tried again next time, the first error is
+    /// returned once the others have been deleted.
+    fn delete_dropped(&mut self) -> io::Result<()> {
+        let dropped = mem::take(&mut *self.dropped.lock().unwrap_or_else(PoisonError::into_inner));
+        let mut failed = Vec::new();
+        let mut result = Ok(());
+        for id in dropped {
+            if self.user_events.remove(&id).is_some() {
+                continue;
+            }
+            match self.sockets.get(&id) {
+                Some(sock) if !sock.state.is_deleting() => {
+                    if let Err(e) = self.delete(id, false) {
+                        failed.push(id);
+                        result = result.and(Err(e));
+                    }
+                }
+                // Deleted already because it was closed.
+                _ => {}
+            }
+        }
+
+        if !failed.is_empty() {
+            let mut dropped = self.dropped.lock().unwrap_or_else(PoisonError::into_inner);
+            dropped.extend(failed);
+        }
+        result
+    }
+
+    pub(crate) fn select(
+        &mut self,
+        events: &mut Events<D::Status>,
+        timeout: Option<Duration>,
+    ) -> io::Result<()> {
+        match timeout.map(|timeout| self.driver.now().checked_add(timeout)) {
+            Some(Some(deadline)) => self.select_until(events, deadline),
+            // Too far away to tell apart from forever.
+            Some(None) | None => {
+                if self.prepare(events)? {
+                    // Events reported already are not kept waiting.
+                    let timeout = match events.is_empty() {
+                        true => None,
+                        false => Some(Duration::ZERO),
+                    };
+                    let n = self.driver.wait(events.statuses_mut(), timeout)?;
+                    self.dispatch(events, n)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Waits for events until `deadline`. A deadline which has passed
+    /// dequeues what is there without blocking.
+    ///
+    /// The driver may return empty handed before the deadline, as the
+    /// completion port does for the part of a timeout below a millisecond,
+    /// so it is waited for again with the time left.
+    pub(crate) fn select_until(
+        &mut self,
+        events: &mut Events<D::Status>,
+        deadline: Instant,
+    ) -> io::Result<()> {
+        if !self.prepare(events)? {
+            return Ok(());
+        }
+        // Events reported already are not kept waiting.
+        let deadline = match events.is_empty() {
+            true => deadline,
+            false => self.driver.now(),
+        };
+
+        loop {
+            let timeout = deadline.saturating_duration_since(self.driver.now());
+            let n = self.driver.wait(events.statuses_mut(), Some(timeout))?;
+            if n > 0 || self.driver.now() >= deadline {
+                return self.dispatch(events, n);
+            }
+        }
+    }
+
+    /// Applies what changed since the last call, returns false if there is
+    /// no room for events.
+    fn prepare(&mut self, events: &mut Events<D::Status>) -> io::Result<bool> {
+        events.reset();
+        self.delete_dropped()?;
+        self.update_events(events)?;
+
+        // Events held back by the last call may fill the buffer already.
+        Ok(!events.statuses_mut().is_empty())
+    }
+
+    /// Reports the `n` statuses dequeued into `events`.
+    fn dispatch(&mut self, events: &mut Events<D::Status>, n: usize) -> io::Result<()> {
+        // Nothing may be reported for a registration dropped while waiting.
+        self.delete_dropped()?;
+
+        for i in 0..n {
+            match self.driver.completion(&events.statuses()[i]) {
+                Some(Completion::Io(completion)) => events.push_completion(completion),
+                Some(completion) => {
+                    if let Some(event) = self.feed_event(completion)? {
+                        events.push_event(event);
+                    }
+                }
+                None => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of registrations whose memory is still held,
+    /// including deleted ones waiting for their poll to complete.
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.sockets.len()
+    }
+
+    fn set_events(&mut self, id: usize, token: Token, interests: Interests) {
+        let epoll_events =
+            interests_to_epoll(interests) | self.trigger_events | EPOLLERR | EPOLLHUP;
+        let sock = self.sockets.get_mut(&id).unwrap();
+
+        if sock
+            .state
+            .set_events(epoll_events, usize::from(token) as u64)
+            && sock.state.request_update()
+        {
+            self.update_queue.push_back(id);
+        }
+    }
+
+    fn update_events(&mut self, events: &mut Events<D::Status>) -> io::Result<()> {
+        while let Some(id) = self.update_queue.pop_front() {
+            if let Some(event) = self.update(id)? {
+                events.push_event(event);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Brings the poll of socket `id` up to date, returns the event to report
+    /// if it turns out to be closed.
+    fn update(&mut self, id: usize) -> io::Result<Option<Event>> {
+        let sock = self.sockets.get_mut(&id).unwrap();
+        let poll_groups = &self.poll_groups;
+        let helper = sock.poll_group.map(|group| poll_groups[group].helper);
+        sock.state.cancel_update();
+
+        match sock.state.update_action()? {
+            UpdateAction::None => Ok(None),
+            UpdateAction::Cancel => {
+                cancel(&mut self.driver, sock, helper, id)?;
+                sock.state.poll_cancelled()?;
+                Ok(None)
+            }
+            UpdateAction::Poll(afd_events) => {
+                let start = match helper {
+                    Some(helper) => {
+                        self.driver
+                            .start_poll(helper, sock.base_socket, afd_events, id)?
+                    }
+                    // Whatever the socket is interested in, the read
+                    // completes with it.
+                    None => self.driver.start_zero_read(sock.base_socket, id)?,
+                };
+                match start {
+                    PollStart::Pending => {
+                        sock.state.poll_started()?;
+                        Ok(None)
+                    }
+                    // Closed while no poll was in flight to tell.
+                    PollStart::SocketClosed => {
+                        let closed = sock.state.user_events & EPOLL_CLOSED_LOCALLY;
+                        let token = Token(sock.state.user_data as usize);
+                        self.delete(id, false)?;
+                        match closed {
+                            0 => Ok(None),
+                            _ => Ok(Some(Event::from_raw(token, closed, None))),
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Marks the socket as deleted, cancelling its poll operation, and frees
+    /// it once no operation refers to it anymore or if `force` is set.
+    fn delete(&mut self, id: usize, force: bool) -> io::Result<()> {
+        let sock = self.sockets.get_mut(&id).unwrap();
+        let poll_groups = &self.poll_groups;
+        let helper = sock.poll_group.map(|group| poll_groups[group].helper);
+
+        if !sock.state.is_deleting() {
+            if sock.state.poll_state == SockPollState::Pending {
+                cancel(&mut self.driver, sock, helper, id)?;
+                sock.state.poll_cancelled()?;
+            }
+            self.update_queue.retain(|&queued| queued != id);
+            sock.state.mark_deleted()?;
+        }
+
+        if sock.state.can_free(force) {
+            let poll_group = sock.poll_group;
+            self.sockets.remove(&id);
+            if let Some(poll_group) = poll_group {
+                self.poll_groups[poll_group].group_size -= 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn feed_event(&mut self, completion: Completion) -> io::Result<Option<Event>> {
+        let (id, result) = match completion {
+            Completion::Poll { id, result } => (id, result),
+            Completion::User { id } => return Ok(self.feed_user_event(id)),
+            // Nobody is waiting for them anymore when shutting down.
+            Completion::Wake | Completion::Io(_) => return Ok(None),
+        };
+        let afd_events = match result {
+            PollResult::Events(afd_events) => Some(afd_events),
+            _ => None,
+        };
+
+        let sock = match self.sockets.get_mut(&id) {
+            Some(sock) => sock,
+            // Only completions of freed sockets can be stale, and sockets are
+            // only freed with a poll in flight when forced to.
+            None => return Ok(None),
+        };
+
+        match sock.state.feed_event(result)? {
+            Feed::Delete => {
+                self.delete(id, false)?;
+                Ok(None)
+            }
+            Feed::Closed(epoll_events) => {
+                let token = Token(sock.state.user_data as usize);
+                self.delete(id, false)?;
+                Ok(Some(Event::from_raw(token, epoll_events, afd_events)))
+            }
+            Feed::Events(epoll_events) => {
+                if sock.state.request_update() {
+                    self.update_queue.push_back(id);
+                }
+
+                match epoll_events {
+                    0 => Ok(None),
+                    _ => Ok(Some(Event::from_raw(
+                        Token(sock.state.user_data as usize),
+                        epoll_events,
+                        afd_events,
+                    ))),
+                }
+            }
+        }
+    }
+
+    fn feed_user_event(&mut self, id: usize) -> Option<Event> {
+        // Packets of dropped user events are left in the driver, ids are
+        // never reused so they are told apart.
+        let user = self.user_events.get(&id)?;
+        let readiness = user.shared.take();
+        match readiness.is_empty() {
+            // Triggered again after the packet was posted, the readiness
+            // was taken with an earlier packet.
+            true => None,
+            false => Some(Event::new(readiness, user.token)),
+        }
+    }
+
+    fn acquire_poll_group(&mut self) -> io::Result<usize> {
+        if let Some(index) = self
+            .poll_groups
+            .iter()
+            .position(|group| group.group_size < self.group_size)
+        {
+            self.poll_groups[index].group_size += 1;
+            return Ok(index);
+        }
+
+        let helper = self.driver.open_helper()?;
+        self.poll_groups.push(PollGroup {
+            helper,
+            group_size: 1,
+        });
+        Ok(self.poll_groups.len() - 1)
+    }
+
+    /// Cancels every poll operation, read and write and waits for their
+    /// completions, so that the driver is left without operations in flight.
+    /// The buffers of reads and writes are dropped with their completions.
+    fn shutdown(&mut self) -> io::Result<()> {
+        let mut ids: Vec<usize> = self.sockets.keys().cloned().collect();
+        ids.sort();
+        for id in ids {
+            self.delete(id, false)?;
+        }
+        self.driver.cancel_ios()?;
+
+        let mut statuses = vec![D::Status::zeroed(); DRAIN_BATCH];
+        while !self.sockets.is_empty() || self.driver.ios_in_flight() {
+            let n = self.driver.wait(&mut statuses, None)?;
+            for status in &statuses[..n] {
+                if let Some(completion) = self.driver.completion(status) {
+                    self.feed_event(completion)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<D: Driver> Drop for Port<D> {
+    fn drop(&mut self) {
+        if self.shutdown().is_err() {
+            // The driver holds on to the memory of operations which never
+            // completed, so the sockets can go.
+            let ids: Vec<usize> = self.sockets.keys().cloned().collect();
+            for id in ids {
+                let _ = self.delete(id, true);
+            }
+        }
+
+        for group in self.poll_groups.drain(..) {
+            self.driver.close_helper(group.helper);
+        }
+    }
+}
+
+/// Cancels the AFD poll or zero byte read in flight for the socket `id`. One
+/// which completed in the meantime has its completion queued all the same.
+fn cancel<D: Driver>(
+    driver: &mut D,
+    sock: &Sock,
+    helper: Option<HANDLE>,
+    id: usize,
+) -> io::Result<()> {
+    let result = match helper {
+        Some(helper) => driver.cancel_poll(helper, id),
+        None => driver.cancel_zero_read(sock.base_socket, id),
+    };
+    match result {
+        Err(ref e) if e.raw_os_error() == Some(ERROR_NOT_FOUND) => Ok(()),
+        result => result,
+    }
+}
+
+pub(crate) fn not_registered() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "socket is not registered")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::buffer_pool::BufferPoolBuilder;
+    use crate::core::builder::DEFAULT_GROUP_SIZE;
+    use crate::core::driver::sim::{SimDriver, SimState, SimStatus};
+    use crate::core::key::Key;
+    use crate::core::ready::Ready;
+    use crate::core::translate::{AFD_POLL_ABORT, AFD_POLL_RECEIVE, AFD_POLL_SEND};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    const NOW: Option<Duration> = Some(Duration::from_millis(0));
+
+    fn port() -> (Port<SimDriver>, Rc<RefCell<SimState>>) {
+        port_with(&SelectorBuilder::default())
+    }
+
+    fn port_with(builder: &SelectorBuilder) -> (Port<SimDriver>, Rc<RefCell<SimState>>) {
+        let (driver, sim) = SimDriver::new();
+        (Port::new(driver, builder, Backend::Afd).unwrap(), sim)
+    }
+
+    fn assert_no_leaks(sim: &Rc<RefCell<SimState>>) {
+        let sim = sim.borrow();
+        assert!(sim.polls.is_empty(), "polls in flight: {:?}", sim.polls);
+        assert!(sim.zero_reads.is_empty());
+        assert!(sim.ios.is_empty());
+        assert!(sim.completions.is_empty());
+        assert!(sim.open_helpers.is_empty());
+        assert_eq!(sim.helpers_opened, sim.helpers_closed);
+        assert_eq!(sim.port_closed, 1);
+    }
+
+    #[test]
+    fn select_reports_signalled_events() -> io::Result<()> {
+        let (mut port, sim) = port();
+        let mut events = Events::with_capacity(8);
+
+        let _registration = port.register(100, Token(1), Interests::READABLE)?;
+        port.select(&mut events, NOW)?;
+        assert!(events.is_empty());
+        assert!(sim.borrow().poll_of(100).is_some());
+
+        sim.borrow_mut().signal(100, AFD_POLL_RECEIVE);
+        port.select(&mut events, NOW)?;
+        assert_eq!(events.len(), 1);
+        let event = events.get(0).unwrap();
+        assert_eq!(event.token(), Token(1));
+        assert!(event.is_readable());
+        assert_eq!(event.raw_afd_events(), Some(AFD_POLL_RECEIVE));
+
+        // The poll is armed again by the next call.
+        assert!(sim.borrow().poll_of(100).is_none());
+        port.select(&mut events, NOW)?;
+        assert!(events.is_empty());
+        assert!(sim.borrow().poll_of(100).is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn drop_cancels_pending_polls_and_closes_handles_once() -> io::Result<()> {
+        let (mut port, sim) = port();
+        let mut events = Events::with_capacity(8);
+
+        let registrations: Vec<Registration> = (0..(2 * DEFAULT_GROUP_SIZE + 1))
+            .map(|socket| port.register(socket, Token(socket), Interests::READABLE))
+            .collect::<io::Result<_>>()?;
+        port.select(&mut events, NOW)?;
+        assert_eq!(sim.borrow().polls.len(), 2 * DEFAULT_GROUP_SIZE + 1);
+        assert_eq!(sim.borrow().helpers_opened, 3);
+
+        drop(port);
+
+        assert_eq!(sim.borrow().polls_cancelled, 2 * DEFAULT_GROUP_SIZE + 1);
+        assert_no_leaks(&sim);
+
+        // Registrations outliving their port are harmless.
+        drop(registrations);
+        Ok(())
+    }
+
+    #[test]
+    fn drop_waits_for_cancellations_already_in_flight() -> io::Result<()> {
+        let (mut port, sim) = port();
+        let mut events = Events::with_capacity(8);
+
+        let mut registration = port.register(7, Token(7), Interests::READABLE)?;
+        port.select(&mut events, NOW)?;
+
+        // The completion of the cancellation has not been dequeued when the
+        // port goes.
+        port.deregister(&mut registration)?;
+        assert_eq!(port.len(), 1);
+        assert_eq!(sim.borrow().completions.len(), 1);
+
+        drop(port);
+        assert_no_leaks(&sim);
+        Ok(())
+    }
+
+    #[test]
+    fn deregister_frees_after_completion() -> io::Result<()> {
+        let (mut port, sim) = port();
+        let mut events = Events::with_capacity(8);
+
+        let mut registration = port.register(3, Token(3), Interests::WRITABLE)?;
+        port.select(&mut events, NOW)?;
+        port.deregister(&mut registration)?;
+        assert_eq!(port.len(), 1);
+
+        port.select(&mut events, NOW)?;
+        assert!(events.is_empty());
+        assert_eq!(port.len(), 0);
+        assert!(sim.borrow().polls.is_empty());
+
+        drop(port);
+        assert_no_leaks(&sim);
+        Ok(())
+    }
+
+    #[test]
+    fn dropped_registration_is_deleted_without_events() -> io::Result<()> {
+        let (mut port, sim) = port();
+        let mut events = Events::with_capacity(8);
+
+        let registration = port.register(9, Token(9), Interests::READABLE)?;
+        port.select(&mut events, NOW)?;
+
+        // Readiness arrives after the owner dropped the socket, but before
+        // the port heard about it.
+        sim.borrow_mut().signal(9, AFD_POLL_RECEIVE);
+        drop(registration);
+        port.select(&mut events, NOW)?;
+        assert!(events.is_empty());
+        assert_eq!(port.len(), 0);
+
+        let registration = port.register(10, Token(10), Interests::READABLE)?;
+        port.select(&mut events, NOW)?;
+        drop(registration);
+        port.select(&mut events, NOW)?;
+        assert!(events.is_empty());
+        assert_eq!(sim.borrow().polls_cancelled, 1);
+        assert_eq!(port.len(), 0);
+
+        drop(port);
+        assert_no_leaks(&sim);
+        Ok(())
+    }
+
+    #[test]
+    fn dropped_registrations_failing_to_delete_are_retried() -> io::Result<()> {
+        let (mut port, sim) = port();
+        let mut events = Events::with_capacity(8);
+
+        let registrations: Vec<Registration> = (1..4)
+            .map(|socket| port.register(socket, Token(socket), Interests::READABLE))
+            .collect::<io::Result<_>>()?;
+        port.select(&mut events, NOW)?;
+
+        // The others are deleted all the same.
+        drop(registrations);
+        sim.borrow_mut().failing_cancels = 1;
+        let err = port.select(&mut events, NOW).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(sim.borrow().polls_cancelled, 2);
+        assert_eq!(sim.borrow().polls.len(), 1);
+
+        port.select(&mut events, NOW)?;
+        assert!(events.is_empty());
+        assert_eq!(sim.borrow().polls_cancelled, 3);
+        assert_eq!(port.len(), 0);
+
+        drop(port);
+        assert_no_leaks(&sim);
+        Ok(())
+    }
+
+    #[test]
+    fn foreign_registrations_are_rejected() -> io::Result<()> {
+        let (mut other, _other_sim) = port();
+        let (mut port, _sim) = port();
+
+        let registration = port.register(1, Token(1), Interests::READABLE)?;
+        let mut foreign = other.register(1, Token(1), Interests::READABLE)?;
+        assert_eq!(registration.id, foreign.id);
+
+        assert!(port
+            .reregister(&foreign, Token(2), Interests::WRITABLE)
+            .is_err());
+        assert!(port.deregister(&mut foreign).is_err());
+        assert!(port
+            .reregister(&registration, Token(2), Interests::WRITABLE)
+            .is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn poll_groups_are_reused() -> io::Result<()> {
+        let (mut port, sim) = port();
+        let mut events = Events::with_capacity(DEFAULT_GROUP_SIZE);
+
+        for round in 0..3 {
+            let registrations: Vec<Registration> = (0..DEFAULT_GROUP_SIZE)
+                .map(|socket| port.register(socket, Token(socket), Interests::WRITABLE))
+                .collect::<io::Result<_>>()?;
+            port.select(&mut events, NOW)?;
+            for mut registration in registrations {
+                port.deregister(&mut registration)?;
+            }
+            port.select(&mut events, NOW)?;
+            assert_eq!(port.len(), 0, "round {}", round);
+        }
+        assert_eq!(sim.borrow().helpers_opened, 1);
+
+        drop(port);
+        assert_no_leaks(&sim);
+        Ok(())
+    }
+
+    #[test]
+    fn group_size_is_configurable() -> io::Result<()> {
+        let (mut port, sim) = port_with(&SelectorBuilder::new().group_size(2).capacity(16));
+        let mut events = Events::with_capacity(8);
+
+        let registrations: Vec<Registration> = (0..5)
+            .map(|socket| port.register(socket, Token(socket), Interests::READABLE))
+            .collect::<io::Result<_>>()?;
+        port.select(&mut events, NOW)?;
+        assert_eq!(sim.borrow().helpers_opened, 3);
+
+        drop(registrations);
+        drop(port);
+        assert_no_leaks(&sim);
+        Ok(())
+    }
+
+    #[test]
+    fn trigger_mode_decides_about_rearming() -> io::Result<()> {
+        let level = SelectorBuilder::new().trigger(Trigger::Level);
+        for &(ref builder, rearmed) in [(SelectorBuilder::new(), false), (level, true)].iter() {
+            let (mut port, sim) = port_with(builder);
+            let mut events = Events::with_capacity(8);
+
+            let _registration = port.register(100, Token(1), Interests::READABLE)?;
+            port.select(&mut events, NOW)?;
+            sim.borrow_mut().signal(100, AFD_POLL_RECEIVE);
+            port.select(&mut events, NOW)?;
+            assert_eq!(events.len(), 1);
+
+            port.select(&mut events, NOW)?;
+            let poll = sim.borrow().poll_of(100).unwrap();
+            assert_eq!(
+                poll.afd_events & AFD_POLL_RECEIVE != 0,
+                rearmed,
+                "{:?}",
+                builder
+            );
+        }
+
+        let (driver, _sim) = SimDriver::new();
+        let edge = SelectorBuilder::new().trigger(Trigger::Edge);
+        assert!(Port::new(driver, &edge, Backend::Afd).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn closed_socket_is_freed() -> io::Result<()> {
+        let (mut port, sim) = port();
+        let mut events = Events::with_capacity(8);
+
+        let registration = port.register(5, Token(5), Interests::READABLE | Interests::WRITABLE)?;
+        port.select(&mut events, NOW)?;
+        assert_eq!(
+            sim.borrow().poll_of(5).unwrap().afd_events & AFD_POLL_SEND,
+            AFD_POLL_SEND
+        );
+
+        sim.borrow_mut().close_socket(5);
+        port.select(&mut events, NOW)?;
+        assert!(events.is_empty());
+        assert_eq!(port.len(), 0);
+        assert!(port
+            .reregister(&registration, Token(5), Interests::READABLE)
+            .is_err());
+
+        // Dropping the registration of a socket which is gone already is
+        // fine as well.
+        drop(registration);
+        port.select(&mut events, NOW)?;
+
+        drop(port);
+        assert_no_leaks(&sim);
+        Ok(())
+    }
+
+    #[test]
+    fn closed_socket_is_reported_on_request() -> io::Result<()> {
+        let (mut port, sim) = port();
+        let mut events = Events::with_capacity(8);
+
+        let interests = Interests::READABLE | Interests::CLOSED_LOCALLY;
+        let registration = port.register(5, Token(5), interests)?;
+        port.select(&mut events, NOW)?;
+
+        sim.borrow_mut().close_socket(5);
+        port.select(&mut events, NOW)?;
+        assert_eq!(events.len(), 1);
+        let ev = events.get(0).unwrap();
+        assert_eq!(ev.token(), Token(5));
+        assert_eq!(ev.readiness(), Ready::CLOSED_LOCALLY);
+        assert_eq!(port.len(), 0);
+
+        drop(registration);
+        port.select(&mut events, NOW)?;
+        assert!(events.is_empty());
+
+        drop(port);
+        assert_no_leaks(&sim);
+        Ok(())
+    }
+
+    #[test]
+    fn socket_closed_between_polls_is_reported_on_request() -> io::Result<()> {
+        let (mut port, sim) = port_with(&SelectorBuilder::new().trigger(Trigger::Level));
+        let mut events = Events::with_capacity(8);
+
+        let interests = Interests::READABLE | Interests::CLOSED_LOCALLY;
+        let _polled = port.register(5, Token(5), interests)?;
+        let _read = port.register_with(6, hidden(), Token(6), interests, Strategy::Auto)?;
+        let _plain = port.register(7, Token(7), Interests::READABLE)?;
+        port.select(&mut events, NOW)?;
+        for socket in 5..8 {
+            sim.borrow_mut().signal(socket, AFD_POLL_RECEIVE);
+        }
+        port.select(&mut events, NOW)?;
+        assert_eq!(events.len(), 3);
+
+        // Nothing is in flight to tell, the polls armed again find out.
+        for socket in 5..8 {
+            sim.borrow_mut().close_socket(socket);
+        }
+        port.select(&mut events, None)?;
+        let reported: Vec<(Token, Ready)> = events
+            .iter()
+            .map(|event| (event.token(), event.readiness()))
+            .collect();
+        assert_eq!(
+            reported,
+            [
+                (Token(5), Ready::CLOSED_LOCALLY),
+                (Token(6), Ready::CLOSED_LOCALLY)
+            ]
+        );
+        assert_eq!(port.len(), 0);
+
+        drop(port);
+        assert_no_leaks(&sim);
+        Ok(())
+    }
+
+    #[test]
+    fn abort_is_reported_as_connection_reset_on_request() -> io::Result<()> {
+        let (mut port, sim) = port();
+        let mut events = Events::with_capacity(8);
+
+        let _plain = port.register(5, Token(5), Interests::READABLE)?;
+        let interests = Interests::READABLE | Interests::CONNECTION_RESET;
+        let _tracked = port.register(6, Token(6), interests)?;
+        port.select(&mut events, NOW)?;
+
+        sim.borrow_mut().signal(5, AFD_POLL_ABORT);
+        sim.borrow_mut().signal(6, AFD_POLL_ABORT);
+        port.select(&mut events, NOW)?;
+        assert_eq!(events.len(), 2);
+        let plain = events.get(0).unwrap();
+        assert_eq!(plain.token(), Token(5));
+        assert!(plain.is_read_closed() && plain.is_write_closed());
+        assert!(!plain.is_connection_reset());
+        let tracked = events.get(1).unwrap();
+        assert_eq!(tracked.token(), Token(6));
+        assert!(tracked.is_read_closed() && tracked.is_write_closed());
+        assert!(tracked.is_connection_reset());
+
+        drop(port);
+        assert_no_leaks(&sim);
+        Ok(())
+    }
+
+    #[test]
+    fn user_event_triggers_are_coalesced() -> io::Result<()> {
+        let (mut port, sim) = port();
+        let mut events = Events::with_capacity(8);
+
+        let user_event = port.user_event(Token(42))?;
+        port.select(&mut events, NOW)?;
+        assert!(events.is_empty());
+
+        user_event.trigger(Ready::READABLE)?;
+        user_event.trigger(Ready::WRITABLE)?;
+        user_event.trigger(Ready::READABLE)?;
+        assert_eq!(sim.borrow().posted.len(), 1);
+
+        port.select(&mut events, NOW)?;
+        assert_eq!(events.len(), 1);
+        let event = events.get(0).unwrap();
+        assert_eq!(event.token(), Token(42));
+        assert_eq!(event.readiness(), Ready::READABLE | Ready::WRITABLE);
+        assert_eq!(event.raw_afd_events(), None);
+
+        // Nothing is left over, the next trigger posts anew.
+        port.select(&mut events, NOW)?;
+        assert!(events.is_empty());
+        user_event.trigger(Ready::PRIORITY)?;
+        port.select(&mut events, NOW)?;
+        assert_eq!(events.get(0).unwrap().readiness(), Ready::PRIORITY);
+
+        drop(user_event);
+        drop(port);
+        assert_no_leaks(&sim);
+        Ok(())
+    }
+
+    #[test]
+    fn user_event_is_triggered_from_other_threads() -> io::Result<()> {
+        let (mut port, sim) = port();
+        let mut events = Events::with_capacity(8);
+
+        let user_event = Arc::new(port.user_event(Token(1))?);
+        let threads: Vec<_> = [Ready::READABLE, Ready::WRITABLE, Ready::ERROR]
+            .iter()
+            .map(|&ready| {
+                let user_event = user_event.clone();
+                std::thread::spawn(move || user_event.trigger(ready))
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap()?;
+        }
+        assert_eq!(sim.borrow().posted.len(), 1);
+
+        port.select(&mut events, NOW)?;
+        assert_eq!(events.len(), 1);
+        assert_eq!(
+            events.get(0).unwrap().readiness(),
+            Ready::READABLE | Ready::WRITABLE | Ready::ERROR
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn dropped_user_event_is_not_reported() -> io::Result<()> {
+        let (mut port, sim) = port();
+        let mut events = Events::with_capacity(8);
+
+        let first = port.user_event(Token(1))?;
+        let second = port.user_event(Token(2))?;
+        first.trigger(Ready::READABLE)?;
+        second.trigger(Ready::READABLE)?;
+        drop(first);
+
+        // The packet of the dropped event is still dequeued, but ignored.
+        port.select(&mut events, NOW)?;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events.get(0).unwrap().token(), Token(2));
+        assert_eq!(port.user_events.len(), 1);
+
+        drop(second);
+        port.select(&mut events, NOW)?;
+        assert!(port.user_events.is_empty());
+
+        drop(port);
+        assert_no_leaks(&sim);
+        Ok(())
+    }
+
+    #[test]
+    fn completions_are_dispatched_by_key() -> io::Result<()> {
+        let (mut port, sim) = port();
+        let mut events = Events::with_capacity(8);
+
+        let _registration = port.register(100, Token(1), Interests::READABLE)?;
+        let user_event = port.user_event(Token(2))?;
+        port.select(&mut events, NOW)?;
+        let id = port.sockets.keys().cloned().next().unwrap();
+
+        // Packets of handles the user associated carry whatever they like,
+        // even what looks like one of ours.
+        sim.borrow_mut().completions.extend(vec![
+            SimStatus::raw(0, id),
+            SimStatus::raw(7, 0xdead_beef),
+            SimStatus::raw(Key::Waker.raw(), 0),
+            // A user event which never existed.
+            SimStatus::raw(Key::User.raw(), 1000),
+        ]);
+        port.select(&mut events, NOW)?;
+        assert!(events.is_empty());
+        assert!(sim.borrow().poll_of(100).is_some());
+
+        sim.borrow_mut().signal(100, AFD_POLL_RECEIVE);
+        user_event.trigger(Ready::WRITABLE)?;
+        sim.borrow_mut()
+            .completions
+            .push_back(SimStatus::raw(Key::Waker.raw(), 0));
+        port.select(&mut events, NOW)?;
+        let reported: Vec<(Token, Ready)> = events
+            .iter()
+            .map(|event| (event.token(), event.readiness()))
+            .collect();
+        assert_eq!(
+            reported,
+            [(Token(1), Ready::READABLE), (Token(2), Ready::WRITABLE)]
+        );
+
+        drop(user_event);
+        drop(port);
+        assert_no_leaks(&sim);
+        Ok(())
+    }
+
+    #[test]
+    fn io_completions_are_reported_beside_events() -> io::Result<()> {
+        let (mut port, sim) = port();
+        let mut events = Events::with_capacity(8);
+
+        let _registration = port.register(100, Token(1), Interests::READABLE)?;
+        let handle = port.associate(200, Token(2))?;
+        port.submit(&handle, IoKind::Read, vec![0; 16].into(), 0)?;
+        port.submit(&handle, IoKind::Write, b"data".to_vec().into(), 16)?;
+        port.select(&mut events, NOW)?;
+        assert!(events.is_empty() && events.completions().len() == 0);
+        assert_eq!(sim.borrow().ios.len(), 2);
+
+        sim.borrow_mut().signal(100, AFD_POLL_RECEIVE);
+        sim.borrow_mut().complete_io(2, Ok(4));
+        let error = io::Error::from(io::ErrorKind::BrokenPipe);
+        sim.borrow_mut().complete_io(1, Err(error));
+        port.select(&mut events, NOW)?;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events.get(0).unwrap().token(), Token(1));
+
+        let completions: Vec<_> = events.drain_completions().collect();
+        assert_eq!(completions.len(), 2);
+        assert_eq!(completions[0].token(), Token(2));
+        assert_eq!(completions[0].kind(), IoKind::Write);
+        assert_eq!(completions[0].result().unwrap(), 4);
+        assert_eq!(completions[0].buf(), b"data");
+        assert_eq!(completions[1].kind(), IoKind::Read);
+        assert_eq!(
+            completions[1].result().unwrap_err().kind(),
+            io::ErrorKind::BrokenPipe
+        );
+        assert!(sim.borrow().ios.is_empty());
+
+        let (mut other, _other_sim) = port_with(&SelectorBuilder::default());
+        let foreign = other.associate(200, Token(3))?;
+        let err = port
+            .submit(&foreign, IoKind::Read, vec![0; 16].into(), 0)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(sim.borrow().ios.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn drop_cancels_reads_and_writes_in_flight() -> io::Result<()> {
+        let (mut port, sim) = port();
+        let mut events = Events::with_capacity(8);
+        let pool = BufferPoolBuilder::new().buffer_size(16).build()?;
+
+        let handle = port.associate(200, Token(2))?;
+        for kind in [IoKind::Read, IoKind::Write, IoKind::Read].iter() {
+            port.submit(&handle, *kind, pool.acquire()?.into(), 0)?;
+        }
+        port.select(&mut events, NOW)?;
+        // One completes, but is never dequeued.
+        sim.borrow_mut().complete_io(2, Ok(16));
+        assert_eq!(pool.stats().outstanding, 3);
+
+        drop(port);
+        assert_eq!(sim.borrow().ios_cancelled, 2);
+        assert_eq!(pool.stats().outstanding, 0);
+        assert_no_leaks(&sim);
+        Ok(())
+    }
+
+    fn hidden() -> io::Result<HANDLE> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "base socket hidden",
+        ))
+    }
+
+    #[test]
+    fn strategy_decides_how_sockets_are_probed() -> io::Result<()> {
+        let (mut port, sim) = port();
+        let mut events = Events::with_capacity(8);
+
+        let interests = Interests::READABLE;
+        let _polled = port.register_with(10, Ok(11), Token(1), interests, Strategy::Auto)?;
+        let _hidden = port.register_with(20, hidden(), Token(2), interests, Strategy::Auto)?;
+        let required = Strategy::ZeroByteRead;
+        let _read = port.register_with(30, Ok(31), Token(3), interests, required)?;
+        assert!(port
+            .register_with(40, hidden(), Token(4), interests, Strategy::Afd)
+            .is_err());
+        port.select(&mut events, NOW)?;
+
+        let sim_ref = sim.borrow();
+        assert!(sim_ref.poll_of(11).is_some());
+        let mut read_sockets: Vec<HANDLE> = sim_ref.zero_reads.values().cloned().collect();
+        read_sockets.sort();
+        assert_eq!(read_sockets, [20, 30]);
+        // Only the AFD poll needed a helper handle.
+        assert_eq!(sim_ref.helpers_opened, 1);
+        drop(sim_ref);
+
+        // Completions of sockets bound to another port never arrive here.
+        sim.borrow_mut().foreign_sockets.insert(50);
+        assert!(port
+            .register_with(50, hidden(), Token(5), interests, Strategy::Auto)
+            .is_err());
+        assert_eq!(port.len(), 3);
+
+        drop(port);
+        assert_no_leaks(&sim);
+        Ok(())
+    }
+
+    #[test]
+    fn cancellations_complete_under_their_own_key() -> io::Result<()> {
+        let (mut port, sim) = port();
+        let mut events = Events::with_capacity(8);
+
+        let interests = Interests::READABLE;
+        let mut polled = port.register(10, Token(1), interests)?;
+        let mut read = port.register_with(20, hidden(), Token(2), interests, Strategy::Auto)?;
+        port.select(&mut events, NOW)?;
+
+        port.deregister(&mut polled)?;
+        port.deregister(&mut read)?;
+        let keys: Vec<Key> = sim
+            .borrow()
+            .completions
+            .iter()
+            .map(|status| Key::from_raw(status.key))
+            .collect();
+        assert_eq!(keys, [Key::Afd, Key::ZeroRead]);
+        assert_eq!(port.len(), 2);
+
+        // Either completion is only found under its key.
+        port.select(&mut events, NOW)?;
+        assert!(events.is_empty());
+        assert_eq!(port.len(), 0);
+
+        drop(port);
+        assert_no_leaks(&sim);
+        Ok(())
+    }
+
+    #[test]
+    fn operations_completing_while_cancelled_are_not_errors() -> io::Result<()> {
+        let (mut port, sim) = port();
+        let mut events = Events::with_capacity(8);
+
+        let interests = Interests::READABLE;
+        let polled = port.register(10, Token(1), interests)?;
+        let mut read = port.register_with(20, hidden(), Token(2), interests, Strategy::Auto)?;
+        port.select(&mut events, NOW)?;
+
+        // Both complete before they are cancelled, the driver has nothing
+        // left to cancel but their completions are still queued.
+        sim.borrow_mut().signal(10, AFD_POLL_RECEIVE);
+        sim.borrow_mut().signal(20, AFD_POLL_RECEIVE);
+        port.reregister(&polled, Token(1), Interests::WRITABLE)?;
+        port.deregister(&mut read)?;
+        port.select(&mut events, NOW)?;
+        assert!(events.is_empty());
+        assert_eq!(port.len(), 1);
+        assert_eq!(sim.borrow().polls_cancelled, 0);
+
+        drop(port);
+        assert_no_leaks(&sim);
+        Ok(())
+    }
+
+    #[test]
+    fn zero_byte_read_reports_readability() -> io::Result<()> {
+        let (mut port, sim) = port();
+        let mut events = Events::with_capacity(8);
+
+        let interests = Interests::READABLE | Interests::CONNECTION_RESET;
+        let mut registration =
+            port.register_with(20, hidden(), Token(2), interests, Strategy::Auto)?;
+        port.select(&mut events, NOW)?;
+        sim.borrow_mut().signal(20, AFD_POLL_SEND);
+        port.select(&mut events, NOW)?;
+        assert!(events.is_empty());
+
+        sim.borrow_mut().signal(20, AFD_POLL_RECEIVE);
+        port.select(&mut events, NOW)?;
+        assert_eq!(events.len(), 1);
+        let event = events.get(0).unwrap();
+        assert_eq!(event.token(), Token(2));
+        assert!(event.is_readable() && !event.is_connection_reset());
+
+        // Triggering once, the read armed again only watches for the
+        // socket to be closed until the registration is renewed.
+        port.select(&mut events, NOW)?;
+        sim.borrow_mut().signal(20, AFD_POLL_RECEIVE);
+        port.select(&mut events, NOW)?;
+        assert!(events.is_empty());
+        let writable = Interests::READABLE | Interests::WRITABLE;
+        let err = port
+            .reregister(&registration, Token(2), writable)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        port.reregister(&registration, Token(2), interests)?;
+        port.select(&mut events, NOW)?;
+        assert_eq!(sim.borrow().zero_reads.len(), 1);
+
+        sim.borrow_mut().signal(20, AFD_POLL_ABORT);
+        port.select(&mut events, NOW)?;
+        assert!(events.get(0).unwrap().is_connection_reset());
+
+        port.reregister(&registration, Token(2), interests)?;
+        port.select(&mut events, NOW)?;
+        port.deregister(&mut registration)?;
+        port.select(&mut events, NOW)?;
+        assert!(events.is_empty());
+        assert_eq!(port.len(), 0);
+
+        drop(port);
+        assert_no_leaks(&sim);
+        Ok(())
+    }
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn select_until_waits_out_early_returns() -> io::Result<()> {
+        let (mut port, sim) = port();
+        let mut events = Events::with_capacity(8);
+
+        // The port gives up waiting only at the deadline, however early the
+        // driver returns, down to waits which do not block at all.
+        let start = sim.borrow().clock.now();
+        sim.borrow_mut().clock.early_returns =
+            vec![ms(3), ms(0), Duration::from_micros(6500)].into();
+        port.select_until(&mut events, start + ms(10))?;
+        assert!(events.is_empty());
+
+        let clock = &sim.borrow().clock;
+        assert_eq!(clock.now(), start + ms(10));
+        let waits = [ms(10), ms(7), ms(7), Duration::from_micros(500)];
+        assert_eq!(
+            clock.waits,
+            waits.iter().cloned().map(Some).collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn select_until_returns_with_the_first_events() -> io::Result<()> {
+        let (mut port, sim) = port();
+        let mut events = Events::with_capacity(8);
+
+        let _registration = port.register(100, Token(1), Interests::READABLE)?;
+        sim.borrow_mut().clock.early_returns = vec![ms(4)].into();
+        let deadline = sim.borrow().clock.now() + ms(10);
+        port.select_until(&mut events, deadline)?;
+        sim.borrow_mut().signal(100, AFD_POLL_RECEIVE);
+        port.select_until(&mut events, deadline)?;
+        assert_eq!(events.len(), 1);
+
+        // The last call dequeued the event without waiting any longer.
+        let clock = &sim.borrow().clock;
+        assert_eq!(clock.now(), deadline);
+        assert_eq!(clock.waits, [Some(ms(10)), Some(ms(6)), Some(ms(0))]);
+        Ok(())
+    }
+
+    #[test]
+    fn zero_timeout_waits_once_without_blocking() -> io::Result<()> {
+        let (mut port, sim) = port();
+        let mut events = Events::with_capacity(8);
+
+        sim.borrow_mut().clock.early_returns = vec![ms(0)].into();
+        port.select(&mut events, Some(Duration::ZERO))?;
+        let past = sim.borrow().clock.now() - ms(1);
+        port.select_until(&mut events, past)?;
+        assert!(events.is_empty());
+
+        let clock = &sim.borrow().clock;
+        assert_eq!(clock.elapsed, Duration::ZERO);
+        assert_eq!(clock.waits, [Some(Duration::ZERO); 2]);
+        Ok(())
+    }
+}
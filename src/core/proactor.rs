@@ -0,0 +1,243 @@
+// Completion based I/O on handles associated with a selector. A read or
+// write takes ownership of its buffer, which the operating system fills or
+// drains while the operation is in flight, and hands it back with the
+// outcome once `select` dequeues the completion. Windows runs the
+// operations as overlapped I/O on the completion port of the selector, other
+// systems on a pool of threads.
+
+use crate::core::afd::HANDLE;
+use crate::core::buffer_pool::PooledBuf;
+use crate::core::token::Token;
+use std::any::Any;
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Whether an operation reads or writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoKind {
+    Read,
+    Write,
+}
+
+/// The buffer of a read or write, which the operation owns while in flight.
+///
+/// A read fills it from the start, a write writes all of it.
+#[derive(Debug)]
+pub enum IoBuf {
+    Vec(Vec<u8>),
+    /// A buffer lent by a `BufferPool`, which goes back to the pool when
+    /// dropped.
+    Pooled(PooledBuf),
+}
+
+impl From<Vec<u8>> for IoBuf {
+    fn from(buf: Vec<u8>) -> IoBuf {
+        IoBuf::Vec(buf)
+    }
+}
+
+impl From<PooledBuf> for IoBuf {
+    fn from(buf: PooledBuf) -> IoBuf {
+        IoBuf::Pooled(buf)
+    }
+}
+
+impl Deref for IoBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match *self {
+            IoBuf::Vec(ref buf) => buf,
+            IoBuf::Pooled(ref buf) => buf,
+        }
+    }
+}
+
+impl DerefMut for IoBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        match *self {
+            IoBuf::Vec(ref mut buf) => buf,
+            IoBuf::Pooled(ref mut buf) => buf,
+        }
+    }
+}
+
+/// Tells selectors apart, handles are only accepted by the selector they
+/// were associated with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SelectorId(usize);
+
+impl SelectorId {
+    pub(crate) fn new() -> SelectorId {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        SelectorId(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// A handle associated with a selector for completion based I/O.
+///
+/// Operations on it can only be submitted to that selector. On Windows the
+/// handle is borrowed, it has to stay open until every operation submitted
+/// on it has completed. Elsewhere the selector works on a duplicate which
+/// stays open as long as the `IoHandle` or an operation on it is around.
+#[derive(Debug, Clone)]
+pub struct IoHandle {
+    pub(crate) handle: HANDLE,
+    pub(crate) token: Token,
+    pub(crate) selector: SelectorId,
+    // Keeps `handle` open where the selector owns it.
+    owner: Option<Arc<dyn Any + Send + Sync>>,
+}
+
+impl IoHandle {
+    pub(crate) fn new(handle: HANDLE, token: Token, selector: SelectorId) -> IoHandle {
+        IoHandle {
+            handle,
+            token,
+            selector,
+            owner: None,
+        }
+    }
+
+    /// Keeps `owner` alive with the handle and every operation on it.
+    #[cfg(any(unix, test))]
+    pub(crate) fn owned_by(mut self, owner: Arc<dyn Any + Send + Sync>) -> IoHandle {
+        self.owner = Some(owner);
+        self
+    }
+
+    /// Returns the token completions on the handle are reported with.
+    pub fn token(&self) -> Token {
+        self.token
+    }
+}
+
+pub(crate) fn not_associated() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "handle is not associated")
+}
+
+/// A read or write on its way to completion.
+#[derive(Debug)]
+pub(crate) struct IoRequest {
+    pub token: Token,
+    pub kind: IoKind,
+    pub buf: IoBuf,
+    pub offset: u64,
+    // The owner of the handle, released once the operation is done.
+    _owner: Option<Arc<dyn Any + Send + Sync>>,
+}
+
+impl IoRequest {
+    pub(crate) fn new(handle: &IoHandle, kind: IoKind, buf: IoBuf, offset: u64) -> IoRequest {
+        IoRequest {
+            token: handle.token,
+            kind,
+            buf,
+            offset,
+            _owner: handle.owner.clone(),
+        }
+    }
+
+    /// Completes the operation with the number of bytes transferred or the
+    /// error it failed with.
+    pub(crate) fn complete(self, result: io::Result<usize>) -> IoCompletion {
+        IoCompletion {
+            token: self.token,
+            kind: self.kind,
+            buf: self.buf,
+            result,
+        }
+    }
+}
+
+/// A read or write which completed, reported by `Selector::select`.
+#[derive(Debug)]
+pub struct IoCompletion {
+    token: Token,
+    kind: IoKind,
+    buf: IoBuf,
+    result: io::Result<usize>,
+}
+
+impl IoCompletion {
+    /// Returns the token of the handle the operation ran on.
+    pub fn token(&self) -> Token {
+        self.token
+    }
+
+    pub fn kind(&self) -> IoKind {
+        self.kind
+    }
+
+    /// Returns the number of bytes transferred, a read at the end of a
+    /// file transfers none.
+    pub fn result(&self) -> Result<usize, &io::Error> {
+        self.result.as_ref().map(|&n| n)
+    }
+
+    /// Returns the buffer of the operation, of which a read filled the
+    /// first `result()` bytes.
+    pub fn buf(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// Takes the buffer back together with the outcome. Dropping a pooled
+    /// buffer gives it back to its pool.
+    pub fn into_parts(self) -> (IoBuf, io::Result<usize>) {
+        (self.buf, self.result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::buffer_pool::BufferPoolBuilder;
+
+    #[test]
+    fn completion_hands_the_buffer_back() {
+        let handle = IoHandle::new(3, Token(9), SelectorId::new());
+        let request = IoRequest::new(&handle, IoKind::Read, b"abc".to_vec().into(), 0);
+
+        let completion = request.complete(Ok(2));
+        assert_eq!(completion.token(), Token(9));
+        assert_eq!(completion.kind(), IoKind::Read);
+        assert_eq!(completion.result().unwrap(), 2);
+        assert_eq!(completion.buf(), b"abc");
+        let (buf, result) = completion.into_parts();
+        assert_eq!((&buf[..], result.unwrap()), (&b"abc"[..], 2));
+    }
+
+    #[test]
+    fn pooled_buffer_goes_back_once_the_operation_is_done() {
+        let pool = BufferPoolBuilder::new().buffer_size(8).build().unwrap();
+        let handle = IoHandle::new(3, Token(1), SelectorId::new());
+
+        let mut buf = pool.acquire().unwrap();
+        buf.set_len(4);
+        let request = IoRequest::new(&handle, IoKind::Write, buf.into(), 0);
+        assert_eq!(request.buf.len(), 4);
+        assert_eq!(pool.stats().outstanding, 1);
+
+        // Completed or cancelled, dropping the operation frees the buffer.
+        drop(request.complete(Ok(4)));
+        assert_eq!(pool.stats().outstanding, 0);
+        let request = IoRequest::new(&handle, IoKind::Read, pool.acquire().unwrap().into(), 0);
+        drop(request);
+        assert_eq!(pool.stats().outstanding, 0);
+        assert_eq!(pool.stats().allocated, 1);
+    }
+
+    #[test]
+    fn operations_keep_the_owner_of_their_handle() {
+        let owner = Arc::new(());
+        let handle = IoHandle::new(3, Token(1), SelectorId::new()).owned_by(owner.clone());
+        let request = IoRequest::new(&handle, IoKind::Read, vec![0; 4].into(), 0);
+        drop(handle);
+        assert_eq!(Arc::strong_count(&owner), 2);
+
+        drop(request.complete(Ok(0)));
+        assert_eq!(Arc::strong_count(&owner), 1);
+    }
+}
@@ -0,0 +1,401 @@
+#[cfg(test)]
+use crate::core::translate::sock_afd_events_to_epoll_events;
+use crate::core::translate::{
+    EPOLLERR, EPOLLHUP, EPOLLIN, EPOLLOUT, EPOLLPRI, EPOLLRDBAND, EPOLLRDHUP, EPOLLRDNORM,
+    EPOLLWRBAND, EPOLLWRNORM, EPOLL_CLOSED_LOCALLY, EPOLL_RESET,
+};
+use std::{fmt, ops};
+
+/// A set of readiness states of an I/O source.
+///
+/// Every state is available on all platforms, a backend which cannot observe
+/// a state simply never reports it.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct Ready(u16);
+
+const EMPTY: u16 = 0b00_0000_0000;
+const READABLE: u16 = 0b00_0000_0001;
+const WRITABLE: u16 = 0b00_0000_0010;
+const ERROR: u16 = 0b00_0000_0100;
+const READ_CLOSED: u16 = 0b00_0000_1000;
+const WRITE_CLOSED: u16 = 0b00_0001_0000;
+const PRIORITY: u16 = 0b00_0010_0000;
+// The following are not available on all platforms.
+const AIO: u16 = 0b00_0100_0000;
+const LIO: u16 = 0b00_1000_0000;
+const CONNECTION_RESET: u16 = 0b01_0000_0000;
+const CLOSED_LOCALLY: u16 = 0b10_0000_0000;
+
+impl Ready {
+    /// Returns an empty `Ready` set.
+    pub const EMPTY: Ready = Ready(EMPTY);
+
+    /// Returns a `Ready` set representing readable readiness.
+    pub const READABLE: Ready = Ready(READABLE);
+
+    /// Returns a `Ready` set representing writable readiness.
+    pub const WRITABLE: Ready = Ready(WRITABLE);
+
+    /// Returns a `Ready` set representing error readiness.
+    pub const ERROR: Ready = Ready(ERROR);
+
+    /// Returns a `Ready` set representing a closed read half.
+    pub const READ_CLOSED: Ready = Ready(READ_CLOSED);
+
+    /// Returns a `Ready` set representing a closed write half.
+    pub const WRITE_CLOSED: Ready = Ready(WRITE_CLOSED);
+
+    /// Returns a `Ready` set representing priority readiness.
+    pub const PRIORITY: Ready = Ready(PRIORITY);
+
+    /// Returns a `Ready` set representing a connection reset by the peer.
+    pub const CONNECTION_RESET: Ready = Ready(CONNECTION_RESET);
+
+    /// Returns a `Ready` set representing a socket closed behind the
+    /// selector's back.
+    pub const CLOSED_LOCALLY: Ready = Ready(CLOSED_LOCALLY);
+
+    /// Returns a `Ready` set representing AIO completion readiness.
+    #[cfg(any(
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "ios",
+        target_os = "macos"
+    ))]
+    pub const AIO: Ready = Ready(AIO);
+
+    /// Returns a `Ready` set representing LIO completion readiness.
+    #[cfg(target_os = "freebsd")]
+    pub const LIO: Ready = Ready(LIO);
+
+    /// Returns true if the `Ready` set is empty.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.0 == EMPTY
+    }
+
+    /// Returns true if the `Ready` set contains readable readiness.
+    #[inline]
+    pub fn is_readable(&self) -> bool {
+        self.contains(Ready::READABLE)
+    }
+
+    /// Returns true if the `Ready` set contains writable readiness.
+    #[inline]
+    pub fn is_writable(&self) -> bool {
+        self.contains(Ready::WRITABLE)
+    }
+
+    /// Returns true if the `Ready` set contains error readiness.
+    ///
+    /// Error events occur when the socket enters an error state. In this case,
+    /// the socket will also receive a readable or writable event. Reading or
+    /// writing to the socket will result in an error.
+    #[inline]
+    pub fn is_error(&self) -> bool {
+        self.contains(Ready::ERROR)
+    }
+
+    /// Returns true if the `Ready` set contains a closed read half.
+    ///
+    /// This occurs when the remote end of a TCP socket shuts down writes, or
+    /// when the connection is reset. Reads will return end of file or an
+    /// error once the buffered data is consumed.
+    #[inline]
+    pub fn is_read_closed(&self) -> bool {
+        self.contains(Ready::READ_CLOSED)
+    }
+
+    /// Returns true if the `Ready` set contains a closed write half.
+    ///
+    /// This occurs when the connection is reset or could not be established,
+    /// writes will fail from now on.
+    #[inline]
+    pub fn is_write_closed(&self) -> bool {
+        self.contains(Ready::WRITE_CLOSED)
+    }
+
+    /// Returns true if the `Ready` set contains priority readiness, meaning
+    /// out-of-band data can be read.
+    #[inline]
+    pub fn is_priority(&self) -> bool {
+        self.contains(Ready::PRIORITY)
+    }
+
+    /// Returns true if the `Ready` set contains a connection reset.
+    ///
+    /// Only reported to registrations with connection reset interests, and
+    /// only by the AFD backend. The read and write halves are reported as
+    /// closed as well.
+    #[inline]
+    pub fn is_connection_reset(&self) -> bool {
+        self.contains(Ready::CONNECTION_RESET)
+    }
+
+    /// Returns true if the `Ready` set tells that the socket has been
+    /// closed behind the selector's back.
+    ///
+    /// Only reported to registrations with closed locally interests, and
+    /// only by the AFD backend. The registration is gone when this is
+    /// reported, no further events will follow.
+    #[inline]
+    pub fn is_closed_locally(&self) -> bool {
+        self.contains(Ready::CLOSED_LOCALLY)
+    }
+
+    /// Returns true if the `Ready` set contains AIO readiness.
+    ///
+    /// # Notes
+    ///
+    /// Method is available on all platforms, but not all platforms (can) use
+    /// this indicator.
+    #[inline]
+    pub fn is_aio(&self) -> bool {
+        self.contains(Ready(AIO))
+    }
+
+    /// Returns true if the `Ready` set contains LIO readiness.
+    ///
+    /// # Notes
+    ///
+    /// Method is available on all platforms, but not all platforms (can) use
+    /// this indicator.
+    #[inline]
+    pub fn is_lio(&self) -> bool {
+        self.contains(Ready(LIO))
+    }
+
+    /// Returns true if `self` is a superset of `other`.
+    ///
+    /// The `other` set may represent more than one readiness operations, in
+    /// which case the function only returns true if `self` contains **all**
+    /// readiness specified in `other`.
+    #[inline]
+    pub fn contains(&self, other: Ready) -> bool {
+        (self.0 & other.0) == other.0
+    }
+
+    #[cfg(any(windows, test))]
+    pub(crate) fn bits(self) -> u16 {
+        self.0
+    }
+
+    #[cfg(any(windows, test))]
+    pub(crate) fn from_bits(bits: u16) -> Ready {
+        Ready(bits)
+    }
+
+    /// Converts an epoll event mask as reported by `epoll_wait` or by the
+    /// AFD translation layer.
+    pub(crate) fn from_epoll(epoll_events: u32) -> Ready {
+        let mut ready = Ready::EMPTY;
+
+        if epoll_events & (EPOLLIN | EPOLLRDNORM) != 0 {
+            ready = ready | Ready::READABLE;
+        }
+        if epoll_events & (EPOLLOUT | EPOLLWRNORM | EPOLLWRBAND) != 0 {
+            ready = ready | Ready::WRITABLE;
+        }
+        if epoll_events & (EPOLLPRI | EPOLLRDBAND) != 0 {
+            ready = ready | Ready::PRIORITY;
+        }
+        if epoll_events & EPOLLERR != 0 {
+            ready = ready | Ready::ERROR;
+        }
+        if epoll_events & (EPOLLHUP | EPOLLRDHUP) != 0 {
+            ready = ready | Ready::READ_CLOSED;
+        }
+        // Linux reports a lone EPOLLERR, or EPOLLERR together with EPOLLOUT,
+        // once the peer refused or reset the connection.
+        if epoll_events & EPOLLHUP != 0
+            || (epoll_events & EPOLLERR != 0 && epoll_events & EPOLLOUT != 0)
+            || epoll_events == EPOLLERR
+        {
+            ready = ready | Ready::WRITE_CLOSED;
+        }
+        if epoll_events & EPOLL_RESET != 0 {
+            ready = ready | Ready::CONNECTION_RESET;
+        }
+        if epoll_events & EPOLL_CLOSED_LOCALLY != 0 {
+            ready = ready | Ready::CLOSED_LOCALLY;
+        }
+
+        ready
+    }
+
+    /// Converts an event mask reported by the AFD driver.
+    // The AFD backend masks events with the user's interest as epoll events
+    // first, so only the tests convert AFD masks directly.
+    #[cfg(test)]
+    pub(crate) fn from_afd(afd_events: u32) -> Ready {
+        Ready::from_epoll(sock_afd_events_to_epoll_events(afd_events))
+    }
+}
+
+impl ops::BitOr for Ready {
+    type Output = Ready;
+
+    #[inline]
+    fn bitor(self, other: Ready) -> Ready {
+        Ready(self.0 | other.0)
+    }
+}
+
+impl ops::BitAnd for Ready {
+    type Output = Ready;
+
+    #[inline]
+    fn bitand(self, other: Ready) -> Ready {
+        Ready(self.0 & other.0)
+    }
+}
+
+impl ops::Sub for Ready {
+    type Output = Ready;
+
+    #[inline]
+    fn sub(self, other: Ready) -> Ready {
+        Ready(self.0 & !other.0)
+    }
+}
+
+impl fmt::Debug for Ready {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut one = false;
+        let flags = [
+            (Ready(READABLE), "Readable"),
+            (Ready(WRITABLE), "Writable"),
+            (Ready(ERROR), "Error"),
+            (Ready(READ_CLOSED), "ReadClosed"),
+            (Ready(WRITE_CLOSED), "WriteClosed"),
+            (Ready(PRIORITY), "Priority"),
+            (Ready(AIO), "AIO"),
+            (Ready(LIO), "LIO"),
+            (Ready(CONNECTION_RESET), "ConnectionReset"),
+            (Ready(CLOSED_LOCALLY), "ClosedLocally"),
+        ];
+
+        for &(flag, msg) in &flags {
+            if self.contains(flag) {
+                if one {
+                    write!(fmt, " | ")?
+                }
+                write!(fmt, "{}", msg)?;
+
+                one = true
+            }
+        }
+
+        if !one {
+            fmt.write_str("(empty)")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::translate::{
+        AFD_POLL_ABORT, AFD_POLL_ACCEPT, AFD_POLL_CONNECT_FAIL, AFD_POLL_DISCONNECT,
+        AFD_POLL_LOCAL_CLOSE, AFD_POLL_RECEIVE, AFD_POLL_RECEIVE_EXPEDITED, AFD_POLL_SEND,
+    };
+
+    #[test]
+    fn afd_bits() {
+        let expected = [
+            (AFD_POLL_RECEIVE, Ready::READABLE),
+            (AFD_POLL_RECEIVE_EXPEDITED, Ready::PRIORITY),
+            (AFD_POLL_SEND, Ready::WRITABLE),
+            (AFD_POLL_DISCONNECT, Ready::READABLE | Ready::READ_CLOSED),
+            (AFD_POLL_ABORT, Ready::READ_CLOSED | Ready::WRITE_CLOSED),
+            (AFD_POLL_LOCAL_CLOSE, Ready::EMPTY),
+            (0x0040, Ready::EMPTY),
+            (AFD_POLL_ACCEPT, Ready::READABLE),
+            (
+                AFD_POLL_CONNECT_FAIL,
+                Ready::READABLE
+                    | Ready::WRITABLE
+                    | Ready::ERROR
+                    | Ready::READ_CLOSED
+                    | Ready::WRITE_CLOSED,
+            ),
+        ];
+
+        for &(afd_events, ready) in expected.iter() {
+            assert_eq!(
+                Ready::from_afd(afd_events),
+                ready,
+                "afd events {:#x}",
+                afd_events
+            );
+        }
+
+        for bit in 9..32 {
+            assert_eq!(Ready::from_afd(1 << bit), Ready::EMPTY, "bit {}", bit);
+        }
+    }
+
+    #[test]
+    fn epoll_bits() {
+        let expected = [
+            (EPOLLIN, Ready::READABLE),
+            (EPOLLRDNORM, Ready::READABLE),
+            (EPOLLPRI, Ready::PRIORITY),
+            (EPOLLRDBAND, Ready::PRIORITY),
+            (EPOLLOUT, Ready::WRITABLE),
+            (EPOLLWRNORM, Ready::WRITABLE),
+            (EPOLLWRBAND, Ready::WRITABLE),
+            (EPOLLERR, Ready::ERROR | Ready::WRITE_CLOSED),
+            (EPOLLHUP, Ready::READ_CLOSED | Ready::WRITE_CLOSED),
+            (EPOLLRDHUP, Ready::READ_CLOSED),
+            (EPOLLIN | EPOLLERR, Ready::READABLE | Ready::ERROR),
+            (
+                EPOLLOUT | EPOLLERR,
+                Ready::WRITABLE | Ready::ERROR | Ready::WRITE_CLOSED,
+            ),
+            (EPOLL_RESET, Ready::CONNECTION_RESET),
+            (EPOLL_CLOSED_LOCALLY, Ready::CLOSED_LOCALLY),
+        ];
+
+        for &(epoll_events, ready) in expected.iter() {
+            assert_eq!(
+                Ready::from_epoll(epoll_events),
+                ready,
+                "epoll events {:#x}",
+                epoll_events
+            );
+        }
+    }
+
+    #[test]
+    fn afd_bits_combine() {
+        let bits = [
+            AFD_POLL_RECEIVE,
+            AFD_POLL_RECEIVE_EXPEDITED,
+            AFD_POLL_SEND,
+            AFD_POLL_DISCONNECT,
+            AFD_POLL_ABORT,
+            AFD_POLL_ACCEPT,
+        ];
+
+        for &a in bits.iter() {
+            for &b in bits.iter() {
+                assert_eq!(
+                    Ready::from_afd(a | b),
+                    Ready::from_afd(a) | Ready::from_afd(b)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn debug_lists_states() {
+        assert_eq!(format!("{:?}", Ready::EMPTY), "(empty)");
+        assert_eq!(
+            format!("{:?}", Ready::READABLE | Ready::READ_CLOSED),
+            "Readable | ReadClosed"
+        );
+    }
+}
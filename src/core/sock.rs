@@ -0,0 +1,324 @@
+use crate::core::poll_state::{InvalidTransition, PollTransition, SockPollState};
+use crate::core::translate::{
+    sock_afd_events_to_epoll_events, sock_epoll_events_to_afd_events, AFD_POLL_ABORT,
+    AFD_POLL_LOCAL_CLOSE, EPOLLERR, EPOLLONESHOT, EPOLL_CLOSED_LOCALLY, EPOLL_RESET,
+    SOCK_KNOWN_EPOLL_EVENTS,
+};
+
+// The events a poll has to be armed for. EPOLL_CLOSED_LOCALLY is left out
+// because AFD_POLL_LOCAL_CLOSE is always armed anyway.
+const SOCK_KNOWN_EVENTS: u32 = SOCK_KNOWN_EPOLL_EVENTS | EPOLL_RESET;
+
+/// What the backend has to do to bring the AFD poll in line with the events
+/// the user is interested in.
+#[derive(Debug, PartialEq)]
+pub(crate) enum UpdateAction {
+    /// The pending poll already covers every event of interest.
+    None,
+    /// The pending poll has to be cancelled, a new one is started once the
+    /// cancellation completes.
+    Cancel,
+    /// No poll is pending, a new one has to be started for these AFD events.
+    Poll(u32),
+}
+
+/// How the AFD poll operation of a socket completed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum PollResult {
+    /// The operation was cancelled with `CancelIoEx`.
+    Cancelled,
+    /// The operation failed with an error status.
+    Failed,
+    /// The operation succeeded and reported these AFD events.
+    Events(u32),
+}
+
+/// What the backend has to do after a poll completion has been fed to a
+/// socket.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Feed {
+    /// The socket has to be deleted.
+    Delete,
+    /// The socket has been closed, it has to be deleted once these epoll
+    /// events have been reported.
+    Closed(u32),
+    /// These epoll events have to be reported, nothing is reported if zero.
+    Events(u32),
+}
+
+/// Bookkeeping of a single socket registered with a selector, this is the
+/// platform independent part of wepoll's `sock_state_t`.
+#[derive(Debug)]
+pub(crate) struct SockState {
+    pub user_events: u32,
+    pub pending_events: u32,
+    pub user_data: u64,
+    pub update_enqueued: bool, //to note if this socket is in selector's update_queue
+    pub poll_state: SockPollState,
+}
+
+impl SockState {
+    pub(crate) fn new() -> SockState {
+        SockState {
+            user_events: 0,
+            pending_events: 0,
+            user_data: 0,
+            update_enqueued: false,
+            poll_state: SockPollState::Idle,
+        }
+    }
+
+    /// Sets the epoll events and user data of the socket, returns true if
+    /// an update has to be requested.
+    pub(crate) fn set_events(&mut self, epoll_events: u32, user_data: u64) -> bool {
+        self.user_events = epoll_events;
+        self.user_data = user_data;
+
+        0 != (self.user_events & SOCK_KNOWN_EVENTS & !self.pending_events)
+    }
+
+    /// Marks the socket as enqueued for an update, returns true if it was not
+    /// enqueued yet and has to be pushed onto the update queue.
+    pub(crate) fn request_update(&mut self) -> bool {
+        if self.update_enqueued {
+            false
+        } else {
+            self.update_enqueued = true;
+            true
+        }
+    }
+
+    /// Takes the socket off the update queue.
+    pub(crate) fn cancel_update(&mut self) {
+        self.update_enqueued = false;
+    }
+
+    pub(crate) fn is_deleting(&self) -> bool {
+        self.poll_state.is_deleting()
+    }
+
+    pub(crate) fn update_action(&self) -> Result<UpdateAction, InvalidTransition> {
+        match self.poll_state {
+            SockPollState::Pending => {
+                if 0 != (self.user_events & SOCK_KNOWN_EVENTS & !self.pending_events) {
+                    Ok(UpdateAction::Cancel)
+                } else {
+                    Ok(UpdateAction::None)
+                }
+            }
+            SockPollState::Cancelled => Ok(UpdateAction::None),
+            SockPollState::Idle => Ok(UpdateAction::Poll(afd_events_of(self.user_events))),
+            state => Err(InvalidTransition {
+                state,
+                transition: PollTransition::Submit,
+            }),
+        }
+    }
+
+    /// Records that a poll for all current user events has been started.
+    pub(crate) fn poll_started(&mut self) -> Result<(), InvalidTransition> {
+        self.poll_state.apply(PollTransition::Submit)?;
+        self.pending_events = self.user_events;
+        Ok(())
+    }
+
+    /// Records that the pending poll has been cancelled.
+    pub(crate) fn poll_cancelled(&mut self) -> Result<(), InvalidTransition> {
+        self.poll_state.apply(PollTransition::Cancel)?;
+        self.pending_events = 0;
+        Ok(())
+    }
+
+    /// Marks the socket for deletion. A pending poll has to be cancelled
+    /// before.
+    pub(crate) fn mark_deleted(&mut self) -> Result<(), InvalidTransition> {
+        self.poll_state.apply(PollTransition::Delete)?;
+        self.update_enqueued = false;
+        Ok(())
+    }
+
+    /// Returns true if the memory of the socket may be released, which is
+    /// only the case once no poll operation refers to it anymore.
+    pub(crate) fn can_free(&self, force: bool) -> bool {
+        force || !self.poll_state.poll_in_flight()
+    }
+
+    pub(crate) fn feed_event(&mut self, result: PollResult) -> Result<Feed, InvalidTransition> {
+        let mut epoll_events: u32 = 0;
+
+        self.poll_state.apply(PollTransition::Complete)?;
+        self.pending_events = 0;
+
+        if self.is_deleting() {
+            return Ok(Feed::Delete);
+        }
+
+        match result {
+            PollResult::Cancelled => {}
+            PollResult::Failed => epoll_events = EPOLLERR,
+            PollResult::Events(afd_events) if afd_events & AFD_POLL_LOCAL_CLOSE != 0 => {
+                return match self.user_events & EPOLL_CLOSED_LOCALLY {
+                    0 => Ok(Feed::Delete),
+                    closed => Ok(Feed::Closed(closed)),
+                };
+            }
+            PollResult::Events(afd_events) => {
+                epoll_events = sock_afd_events_to_epoll_events(afd_events);
+                if afd_events & AFD_POLL_ABORT != 0 {
+                    epoll_events |= EPOLL_RESET;
+                }
+            }
+        }
+
+        epoll_events &= self.user_events;
+
+        if epoll_events != 0 && self.user_events & EPOLLONESHOT != 0 {
+            self.user_events = 0;
+        }
+
+        Ok(Feed::Events(epoll_events))
+    }
+}
+
+/// Returns the AFD events to poll for to observe `epoll_events`, including
+/// the events which are not part of epoll.
+fn afd_events_of(epoll_events: u32) -> u32 {
+    let mut afd_events = sock_epoll_events_to_afd_events(epoll_events);
+    if epoll_events & EPOLL_RESET != 0 {
+        afd_events |= AFD_POLL_ABORT;
+    }
+    afd_events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::poll_state::tests::Rng;
+    use crate::core::translate::{EPOLLIN, EPOLLOUT};
+
+    /// Drives a socket the way the AFD backend does, with the driver
+    /// completing polls at random.
+    struct Backend {
+        sock: SockState,
+        deleted: bool,
+    }
+
+    impl Backend {
+        fn update(&mut self) -> Result<(), InvalidTransition> {
+            self.sock.cancel_update();
+            match self.sock.update_action()? {
+                UpdateAction::None => Ok(()),
+                UpdateAction::Cancel => self.sock.poll_cancelled(),
+                UpdateAction::Poll(_) => self.sock.poll_started(),
+            }
+        }
+
+        fn delete(&mut self) -> Result<(), InvalidTransition> {
+            if !self.sock.is_deleting() {
+                if self.sock.poll_state == SockPollState::Pending {
+                    self.sock.poll_cancelled()?;
+                }
+                self.sock.mark_deleted()?;
+            }
+            self.deleted = self.sock.can_free(false);
+            Ok(())
+        }
+
+        fn complete(&mut self, result: PollResult) -> Result<(), InvalidTransition> {
+            match self.sock.feed_event(result)? {
+                Feed::Delete | Feed::Closed(_) => self.delete(),
+                Feed::Events(_) => {
+                    self.sock.request_update();
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn delete_waits_for_cancelled_poll() {
+        let mut backend = Backend {
+            sock: SockState::new(),
+            deleted: false,
+        };
+
+        backend.sock.set_events(EPOLLIN, 0);
+        backend.update().unwrap();
+        assert_eq!(backend.sock.poll_state, SockPollState::Pending);
+
+        backend.delete().unwrap();
+        assert!(!backend.deleted);
+        assert!(backend.sock.update_action().is_err());
+
+        backend.complete(PollResult::Cancelled).unwrap();
+        assert!(backend.deleted);
+    }
+
+    #[test]
+    fn close_and_abort_are_reported_on_request() {
+        let mut sock = SockState::new();
+        sock.set_events(EPOLLIN, 0);
+        sock.poll_started().unwrap();
+        let aborted = sock.feed_event(PollResult::Events(AFD_POLL_ABORT)).unwrap();
+        assert_eq!(aborted, Feed::Events(0));
+        sock.poll_started().unwrap();
+        let closed = sock.feed_event(PollResult::Events(AFD_POLL_LOCAL_CLOSE));
+        assert_eq!(closed, Ok(Feed::Delete));
+
+        let mut sock = SockState::new();
+        sock.set_events(EPOLLIN | EPOLL_RESET | EPOLL_CLOSED_LOCALLY, 0);
+        assert_eq!(
+            sock.update_action().unwrap(),
+            UpdateAction::Poll(afd_events_of(EPOLLIN) | AFD_POLL_ABORT)
+        );
+        sock.poll_started().unwrap();
+        let aborted = sock.feed_event(PollResult::Events(AFD_POLL_ABORT)).unwrap();
+        assert_eq!(aborted, Feed::Events(EPOLL_RESET));
+        sock.poll_started().unwrap();
+        let closed = sock.feed_event(PollResult::Events(AFD_POLL_LOCAL_CLOSE));
+        assert_eq!(closed, Ok(Feed::Closed(EPOLL_CLOSED_LOCALLY)));
+    }
+
+    #[test]
+    fn backend_never_violates_the_state_machine() {
+        for seed in 0..500 {
+            let mut rng = Rng::new(seed);
+            let mut backend = Backend {
+                sock: SockState::new(),
+                deleted: false,
+            };
+
+            for _ in 0..64 {
+                if backend.deleted {
+                    break;
+                }
+
+                let in_flight = backend.sock.poll_state.poll_in_flight();
+                let deleting = backend.sock.is_deleting();
+                let result = match rng.below(5) {
+                    0 if !deleting => {
+                        let events = [0, EPOLLIN, EPOLLOUT, EPOLLIN | EPOLLOUT];
+                        let events = events[rng.below(4) as usize];
+                        if backend.sock.set_events(events, seed) {
+                            backend.sock.request_update();
+                        }
+                        Ok(())
+                    }
+                    1 if !deleting && backend.sock.update_enqueued => backend.update(),
+                    2 if in_flight => {
+                        let result = match rng.below(3) {
+                            0 => PollResult::Cancelled,
+                            1 => PollResult::Failed,
+                            _ => PollResult::Events(rng.next() as u32),
+                        };
+                        backend.complete(result)
+                    }
+                    3 => backend.delete(),
+                    _ => Ok(()),
+                };
+
+                assert_eq!(result, Ok(()), "seed {}", seed);
+            }
+        }
+    }
+}
@@ -0,0 +1,124 @@
+// How the readiness of a registered socket is observed. AFD polling needs
+// the base socket under all layered service providers, which some of them
+// hide. Those sockets can still be probed with the zero byte `WSARecv`
+// trick: a receive into an empty buffer completes once data has arrived,
+// without consuming any.
+
+use crate::core::afd::HANDLE;
+use crate::core::interests::Interests;
+use crate::core::translate::{
+    AFD_POLL_ABORT, AFD_POLL_DISCONNECT, AFD_POLL_LOCAL_CLOSE, AFD_POLL_RECEIVE,
+};
+use std::io;
+
+/// The AFD events a completed zero byte read stands for, depending on how
+/// it completed.
+pub(crate) const ZERO_BYTE_READ_EVENTS: u32 =
+    AFD_POLL_RECEIVE | AFD_POLL_DISCONNECT | AFD_POLL_ABORT | AFD_POLL_LOCAL_CLOSE;
+
+/// How a selector observes the readiness of a socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Strategy {
+    /// AFD polling where the base socket can be resolved, zero byte reads
+    /// otherwise.
+    #[default]
+    Auto,
+    /// AFD polling of the base socket, as `epoll` would.
+    Afd,
+    /// A zero byte receive on the socket itself, which completes once it is
+    /// readable. Writability and urgent data cannot be observed this way.
+    ZeroByteRead,
+}
+
+/// A strategy decided for a socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Probe {
+    /// Poll this base socket through AFD.
+    Afd(HANDLE),
+    /// Arm zero byte reads on the socket.
+    ZeroByteRead,
+}
+
+/// Interests a zero byte read can tell about.
+const ZERO_BYTE_READ_INTERESTS: Interests = Interests::READABLE
+    .add(Interests::CONNECTION_RESET)
+    .add(Interests::CLOSED_LOCALLY);
+
+impl Strategy {
+    /// Decides how a socket registered for `interests` is probed, given the
+    /// outcome of resolving its base socket.
+    pub(crate) fn probe(
+        self,
+        base_socket: io::Result<HANDLE>,
+        interests: Interests,
+    ) -> io::Result<Probe> {
+        match (self, base_socket) {
+            (Strategy::Afd, base_socket) => base_socket.map(Probe::Afd),
+            (Strategy::Auto, Ok(base_socket)) => Ok(Probe::Afd(base_socket)),
+            // The base socket is the better error to report.
+            (Strategy::Auto, Err(err)) if !zero_byte_read_observes(interests) => Err(err),
+            (Strategy::Auto, Err(_)) => Ok(Probe::ZeroByteRead),
+            (Strategy::ZeroByteRead, _) => {
+                check_zero_byte_read(interests)?;
+                Ok(Probe::ZeroByteRead)
+            }
+        }
+    }
+}
+
+fn zero_byte_read_observes(interests: Interests) -> bool {
+    ZERO_BYTE_READ_INTERESTS.contains(interests)
+}
+
+/// Fails for interests a zero byte read cannot tell about.
+pub(crate) fn check_zero_byte_read(interests: Interests) -> io::Result<()> {
+    match zero_byte_read_observes(interests) {
+        true => Ok(()),
+        false => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "zero byte reads only observe readability",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hidden() -> io::Result<HANDLE> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "hidden"))
+    }
+
+    #[test]
+    fn auto_falls_back_to_zero_byte_reads() {
+        let readable = Interests::READABLE | Interests::CONNECTION_RESET;
+        assert_eq!(
+            Strategy::Auto.probe(Ok(8), readable).unwrap(),
+            Probe::Afd(8)
+        );
+        assert_eq!(
+            Strategy::Auto.probe(hidden(), readable).unwrap(),
+            Probe::ZeroByteRead
+        );
+
+        // Nothing would ever report the writability.
+        let writable = Interests::READABLE | Interests::WRITABLE;
+        let err = Strategy::Auto.probe(hidden(), writable).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn required_strategy_is_kept() {
+        assert!(Strategy::Afd.probe(hidden(), Interests::READABLE).is_err());
+        assert_eq!(
+            Strategy::ZeroByteRead
+                .probe(Ok(8), Interests::READABLE)
+                .unwrap(),
+            Probe::ZeroByteRead
+        );
+        let err = Strategy::ZeroByteRead
+            .probe(Ok(8), Interests::PRIORITY)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
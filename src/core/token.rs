@@ -1,3 +1,5 @@
+/// Tells registrations apart in the events. The epoll selector reserves
+/// `Token(usize::MAX)` for itself.
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Token(pub usize);
 
@@ -0,0 +1,540 @@
+// Translation between the epoll event masks exposed to users and the event
+// masks understood by the AFD driver. Nothing in here touches Windows types,
+// so the rules can be checked on any host.
+
+use crate::core::interests::Interests;
+
+#[cfg(any(windows, test))]
+pub(crate) const AFD_POLL_RECEIVE: u32 = 0x0001;
+#[cfg(any(windows, test))]
+pub(crate) const AFD_POLL_RECEIVE_EXPEDITED: u32 = 0x0002;
+#[cfg(any(windows, test))]
+pub(crate) const AFD_POLL_SEND: u32 = 0x0004;
+#[cfg(any(windows, test))]
+pub(crate) const AFD_POLL_DISCONNECT: u32 = 0x0008;
+#[cfg(any(windows, test))]
+pub(crate) const AFD_POLL_ABORT: u32 = 0x0010;
+#[cfg(any(windows, test))]
+pub(crate) const AFD_POLL_LOCAL_CLOSE: u32 = 0x0020;
+#[cfg(any(windows, test))]
+pub(crate) const AFD_POLL_ACCEPT: u32 = 0x0080;
+#[cfg(any(windows, test))]
+pub(crate) const AFD_POLL_CONNECT_FAIL: u32 = 0x0100;
+
+pub(crate) const EPOLLIN: u32 = 0b1;
+pub(crate) const EPOLLPRI: u32 = 0b10;
+pub(crate) const EPOLLOUT: u32 = 0b100;
+pub(crate) const EPOLLERR: u32 = 0b1000;
+pub(crate) const EPOLLHUP: u32 = 0b10000;
+pub(crate) const EPOLLRDNORM: u32 = 0b1000000;
+pub(crate) const EPOLLRDBAND: u32 = 0b10000000;
+pub(crate) const EPOLLWRNORM: u32 = 0b100000000;
+pub(crate) const EPOLLWRBAND: u32 = 0b1000000000;
+#[cfg(any(windows, test))]
+pub(crate) const EPOLLMSG: u32 = 0b10000000000;
+pub(crate) const EPOLLRDHUP: u32 = 0b10000000000000;
+#[cfg(any(windows, test))]
+pub(crate) const EPOLLONESHOT: u32 = 0b10000000000000000000000000000000;
+
+// Not part of epoll: the kernel leaves these bits unused, the AFD backend
+// reports them for AFD_POLL_ABORT and AFD_POLL_LOCAL_CLOSE when asked to.
+pub(crate) const EPOLL_RESET: u32 = 1 << 24;
+pub(crate) const EPOLL_CLOSED_LOCALLY: u32 = 1 << 25;
+#[cfg(unix)]
+pub(crate) const EPOLL_EXTENSIONS: u32 = EPOLL_RESET | EPOLL_CLOSED_LOCALLY;
+
+#[cfg(any(windows, test))]
+pub(crate) const SOCK_KNOWN_EPOLL_EVENTS: u32 = EPOLLIN
+    | EPOLLPRI
+    | EPOLLOUT
+    | EPOLLERR
+    | EPOLLHUP
+    | EPOLLRDNORM
+    | EPOLLRDBAND
+    | EPOLLWRNORM
+    | EPOLLWRBAND
+    | EPOLLMSG
+    | EPOLLRDHUP;
+
+/// Returns the AFD events which have to be polled for to observe
+/// `epoll_events`.
+#[cfg(any(windows, test))]
+pub(crate) fn sock_epoll_events_to_afd_events(epoll_events: u32) -> u32 {
+    /* Always monitor for AFD_POLL_LOCAL_CLOSE, which is triggered when the
+     * socket is closed with closesocket() or CloseHandle(). */
+    let mut afd_events = AFD_POLL_LOCAL_CLOSE;
+
+    if 0 != (epoll_events & (EPOLLIN | EPOLLRDNORM)) {
+        afd_events |= AFD_POLL_RECEIVE | AFD_POLL_ACCEPT;
+    }
+    if 0 != (epoll_events & (EPOLLPRI | EPOLLRDBAND)) {
+        afd_events |= AFD_POLL_RECEIVE_EXPEDITED;
+    }
+    if 0 != (epoll_events & (EPOLLOUT | EPOLLWRNORM | EPOLLWRBAND)) {
+        afd_events |= AFD_POLL_SEND;
+    }
+    if 0 != (epoll_events & (EPOLLIN | EPOLLRDNORM | EPOLLRDHUP)) {
+        afd_events |= AFD_POLL_DISCONNECT;
+    }
+    if 0 != (epoll_events & EPOLLHUP) {
+        afd_events |= AFD_POLL_ABORT;
+    }
+    if 0 != (epoll_events & EPOLLERR) {
+        afd_events |= AFD_POLL_CONNECT_FAIL;
+    }
+
+    afd_events
+}
+
+/// Returns the epoll events reported by the AFD driver as `afd_events`.
+#[cfg(any(windows, test))]
+pub(crate) fn sock_afd_events_to_epoll_events(afd_events: u32) -> u32 {
+    let mut epoll_events: u32 = 0;
+
+    if 0 != (afd_events & (AFD_POLL_RECEIVE | AFD_POLL_ACCEPT)) {
+        epoll_events |= EPOLLIN | EPOLLRDNORM;
+    }
+    if 0 != (afd_events & AFD_POLL_RECEIVE_EXPEDITED) {
+        epoll_events |= EPOLLPRI | EPOLLRDBAND;
+    }
+    if 0 != (afd_events & AFD_POLL_SEND) {
+        epoll_events |= EPOLLOUT | EPOLLWRNORM | EPOLLWRBAND;
+    }
+    if 0 != (afd_events & AFD_POLL_DISCONNECT) {
+        epoll_events |= EPOLLIN | EPOLLRDNORM | EPOLLRDHUP;
+    }
+    if 0 != (afd_events & AFD_POLL_ABORT) {
+        epoll_events |= EPOLLHUP;
+    }
+    if 0 != (afd_events & AFD_POLL_CONNECT_FAIL) {
+        /* Linux reports all these events after connect() has failed. */
+        epoll_events |= EPOLLIN | EPOLLOUT | EPOLLERR | EPOLLRDNORM | EPOLLWRNORM | EPOLLRDHUP;
+    }
+
+    epoll_events
+}
+
+// The `WSAPOLLFD` flags, for the WSAPoll fallback of the AFD backend.
+#[cfg(any(windows, test))]
+pub(crate) const POLLERR: u16 = 0x0001;
+#[cfg(any(windows, test))]
+pub(crate) const POLLHUP: u16 = 0x0002;
+#[cfg(any(windows, test))]
+pub(crate) const POLLNVAL: u16 = 0x0004;
+#[cfg(any(windows, test))]
+pub(crate) const POLLWRNORM: u16 = 0x0010;
+#[cfg(any(windows, test))]
+pub(crate) const POLLRDNORM: u16 = 0x0100;
+#[cfg(any(windows, test))]
+pub(crate) const POLLRDBAND: u16 = 0x0200;
+
+/// Returns the `WSAPOLLFD` events which observe `afd_events`. Errors and
+/// hang-ups cannot be asked for, WSAPoll always reports them, which covers
+/// AFD_POLL_DISCONNECT as well.
+#[cfg(any(windows, test))]
+pub(crate) fn afd_events_to_poll_events(afd_events: u32) -> u16 {
+    let mut poll_events = 0;
+
+    if 0 != (afd_events & (AFD_POLL_RECEIVE | AFD_POLL_ACCEPT)) {
+        poll_events |= POLLRDNORM;
+    }
+    if 0 != (afd_events & AFD_POLL_RECEIVE_EXPEDITED) {
+        poll_events |= POLLRDBAND;
+    }
+    if 0 != (afd_events & AFD_POLL_SEND) {
+        poll_events |= POLLWRNORM;
+    }
+
+    poll_events
+}
+
+/// Returns the AFD events matching the `revents` reported by WSAPoll.
+/// WSAPoll does not tell a graceful disconnect from an abort, so a hang-up
+/// is reported as both.
+#[cfg(any(windows, test))]
+pub(crate) fn poll_events_to_afd_events(poll_events: u16) -> u32 {
+    let mut afd_events = 0;
+
+    if 0 != (poll_events & POLLRDNORM) {
+        afd_events |= AFD_POLL_RECEIVE | AFD_POLL_ACCEPT;
+    }
+    if 0 != (poll_events & POLLRDBAND) {
+        afd_events |= AFD_POLL_RECEIVE_EXPEDITED;
+    }
+    if 0 != (poll_events & POLLWRNORM) {
+        afd_events |= AFD_POLL_SEND;
+    }
+    if 0 != (poll_events & POLLHUP) {
+        afd_events |= AFD_POLL_DISCONNECT | AFD_POLL_ABORT;
+    }
+    if 0 != (poll_events & POLLERR) {
+        afd_events |= AFD_POLL_CONNECT_FAIL;
+    }
+    if 0 != (poll_events & POLLNVAL) {
+        afd_events |= AFD_POLL_LOCAL_CLOSE;
+    }
+
+    afd_events
+}
+
+pub(crate) fn interests_to_epoll(interests: Interests) -> u32 {
+    let mut kind = 0;
+
+    if interests.is_readable() {
+        kind |= EPOLLIN;
+    }
+
+    if interests.is_writable() {
+        kind |= EPOLLOUT;
+    }
+
+    if interests.is_priority() {
+        kind |= EPOLLPRI;
+    }
+
+    if interests.is_connection_reset() {
+        kind |= EPOLL_RESET;
+    }
+
+    if interests.is_closed_locally() {
+        kind |= EPOLL_CLOSED_LOCALLY;
+    }
+
+    kind
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    // Every epoll bit a user may pass in, including the flag bits which are
+    // not events themselves.
+    const EPOLL_BITS: [u32; 12] = [
+        EPOLLIN,
+        EPOLLPRI,
+        EPOLLOUT,
+        EPOLLERR,
+        EPOLLHUP,
+        EPOLLRDNORM,
+        EPOLLRDBAND,
+        EPOLLWRNORM,
+        EPOLLWRBAND,
+        EPOLLMSG,
+        EPOLLRDHUP,
+        EPOLLONESHOT,
+    ];
+
+    // The low ten AFD bits, including 0x0040 and 0x0200 which wepoll never
+    // asks for and which must be ignored when reported.
+    const AFD_BITS: [u32; 10] = [
+        AFD_POLL_RECEIVE,
+        AFD_POLL_RECEIVE_EXPEDITED,
+        AFD_POLL_SEND,
+        AFD_POLL_DISCONNECT,
+        AFD_POLL_ABORT,
+        AFD_POLL_LOCAL_CLOSE,
+        0x0040,
+        AFD_POLL_ACCEPT,
+        AFD_POLL_CONNECT_FAIL,
+        0x0200,
+    ];
+
+    // Taken from `sock__epoll_events_to_afd_events` in wepoll's sock.c.
+    const EPOLL_TO_AFD_GOLDEN: [(u32, u32); 12] = [
+        (
+            EPOLLIN,
+            AFD_POLL_RECEIVE | AFD_POLL_ACCEPT | AFD_POLL_DISCONNECT,
+        ),
+        (EPOLLPRI, AFD_POLL_RECEIVE_EXPEDITED),
+        (EPOLLOUT, AFD_POLL_SEND),
+        (EPOLLERR, AFD_POLL_CONNECT_FAIL),
+        (EPOLLHUP, AFD_POLL_ABORT),
+        (
+            EPOLLRDNORM,
+            AFD_POLL_RECEIVE | AFD_POLL_ACCEPT | AFD_POLL_DISCONNECT,
+        ),
+        (EPOLLRDBAND, AFD_POLL_RECEIVE_EXPEDITED),
+        (EPOLLWRNORM, AFD_POLL_SEND),
+        (EPOLLWRBAND, AFD_POLL_SEND),
+        (EPOLLMSG, 0),
+        (EPOLLRDHUP, AFD_POLL_DISCONNECT),
+        (EPOLLONESHOT, 0),
+    ];
+
+    // Taken from `sock__afd_events_to_epoll_events` in wepoll's sock.c.
+    const AFD_TO_EPOLL_GOLDEN: [(u32, u32); 10] = [
+        (AFD_POLL_RECEIVE, EPOLLIN | EPOLLRDNORM),
+        (AFD_POLL_RECEIVE_EXPEDITED, EPOLLPRI | EPOLLRDBAND),
+        (AFD_POLL_SEND, EPOLLOUT | EPOLLWRNORM | EPOLLWRBAND),
+        (AFD_POLL_DISCONNECT, EPOLLIN | EPOLLRDNORM | EPOLLRDHUP),
+        (AFD_POLL_ABORT, EPOLLHUP),
+        (AFD_POLL_LOCAL_CLOSE, 0),
+        (0x0040, 0),
+        (AFD_POLL_ACCEPT, EPOLLIN | EPOLLRDNORM),
+        (
+            AFD_POLL_CONNECT_FAIL,
+            EPOLLIN | EPOLLOUT | EPOLLERR | EPOLLRDNORM | EPOLLWRNORM | EPOLLRDHUP,
+        ),
+        (0x0200, 0),
+    ];
+
+    // Builds the mask made of the bits of `bits` selected by `n`.
+    fn combination(bits: &[u32], n: u32) -> u32 {
+        bits.iter()
+            .enumerate()
+            .filter(|&(i, _)| n & (1 << i) != 0)
+            .fold(0, |mask, (_, &bit)| mask | bit)
+    }
+
+    fn epoll_combinations() -> impl Iterator<Item = u32> {
+        (0..1 << EPOLL_BITS.len()).map(|n| combination(&EPOLL_BITS, n))
+    }
+
+    fn afd_combinations() -> impl Iterator<Item = u32> {
+        (0..1 << AFD_BITS.len()).map(|n| combination(&AFD_BITS, n))
+    }
+
+    fn bits_of(mask: u32) -> impl Iterator<Item = u32> {
+        (0..32).map(|i| 1 << i).filter(move |bit| mask & bit != 0)
+    }
+
+    #[test]
+    fn epoll_to_afd_golden() {
+        assert_eq!(sock_epoll_events_to_afd_events(0), AFD_POLL_LOCAL_CLOSE);
+        for &(epoll_events, afd_events) in EPOLL_TO_AFD_GOLDEN.iter() {
+            assert_eq!(
+                sock_epoll_events_to_afd_events(epoll_events),
+                afd_events | AFD_POLL_LOCAL_CLOSE,
+                "epoll events {:#x}",
+                epoll_events
+            );
+        }
+    }
+
+    #[test]
+    fn afd_to_epoll_golden() {
+        assert_eq!(sock_afd_events_to_epoll_events(0), 0);
+        for &(afd_events, epoll_events) in AFD_TO_EPOLL_GOLDEN.iter() {
+            assert_eq!(
+                sock_afd_events_to_epoll_events(afd_events),
+                epoll_events,
+                "afd events {:#x}",
+                afd_events
+            );
+        }
+    }
+
+    #[test]
+    fn local_close_is_always_monitored() {
+        for epoll_events in epoll_combinations() {
+            let afd_events = sock_epoll_events_to_afd_events(epoll_events);
+            assert_ne!(afd_events & AFD_POLL_LOCAL_CLOSE, 0, "{:#x}", epoll_events);
+        }
+    }
+
+    #[test]
+    fn translations_are_bitwise() {
+        // Both translations must treat every bit independently, so the golden
+        // tables above fully describe them.
+        for epoll_events in epoll_combinations() {
+            let expected = bits_of(epoll_events)
+                .map(sock_epoll_events_to_afd_events)
+                .fold(AFD_POLL_LOCAL_CLOSE, |mask, afd_events| mask | afd_events);
+            assert_eq!(
+                sock_epoll_events_to_afd_events(epoll_events),
+                expected,
+                "{:#x}",
+                epoll_events
+            );
+        }
+
+        for afd_events in afd_combinations() {
+            let expected = bits_of(afd_events)
+                .map(sock_afd_events_to_epoll_events)
+                .fold(0, |mask, epoll_events| mask | epoll_events);
+            assert_eq!(
+                sock_afd_events_to_epoll_events(afd_events),
+                expected,
+                "{:#x}",
+                afd_events
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_bits_are_ignored() {
+        let unknown_epoll = !(SOCK_KNOWN_EPOLL_EVENTS | EPOLLONESHOT);
+        for epoll_events in epoll_combinations() {
+            assert_eq!(
+                sock_epoll_events_to_afd_events(epoll_events | unknown_epoll),
+                sock_epoll_events_to_afd_events(epoll_events)
+            );
+        }
+
+        let known_afd = AFD_BITS.iter().fold(0, |mask, bit| mask | bit);
+        for afd_events in afd_combinations() {
+            assert_eq!(
+                sock_afd_events_to_epoll_events(afd_events | !known_afd),
+                sock_afd_events_to_epoll_events(afd_events)
+            );
+        }
+    }
+
+    #[test]
+    fn epoll_round_trip_reports_every_requested_event() {
+        // EPOLLMSG is accepted but there is no AFD event which could ever
+        // report it.
+        let reportable = SOCK_KNOWN_EPOLL_EVENTS & !EPOLLMSG;
+        for epoll_events in epoll_combinations() {
+            let afd_events = sock_epoll_events_to_afd_events(epoll_events);
+            let reported = sock_afd_events_to_epoll_events(afd_events);
+            assert_eq!(
+                epoll_events & reportable & !reported,
+                0,
+                "{:#x} round-trips to {:#x}",
+                epoll_events,
+                reported
+            );
+        }
+    }
+
+    #[test]
+    fn armed_afd_events_never_wake_up_spuriously() {
+        // Whenever one of the AFD events armed for a socket fires, at least
+        // one of the epoll events the user asked for must be reported, the
+        // only exception being LOCAL_CLOSE which is handled separately.
+        for epoll_events in epoll_combinations() {
+            let armed = sock_epoll_events_to_afd_events(epoll_events) & !AFD_POLL_LOCAL_CLOSE;
+            for afd_event in bits_of(armed) {
+                assert_ne!(
+                    sock_afd_events_to_epoll_events(afd_event) & epoll_events,
+                    0,
+                    "{:#x} armed by {:#x}",
+                    afd_event,
+                    epoll_events
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn afd_round_trip_rearms_every_reported_event() {
+        let known_afd = AFD_POLL_RECEIVE
+            | AFD_POLL_RECEIVE_EXPEDITED
+            | AFD_POLL_SEND
+            | AFD_POLL_DISCONNECT
+            | AFD_POLL_ABORT
+            | AFD_POLL_LOCAL_CLOSE
+            | AFD_POLL_ACCEPT
+            | AFD_POLL_CONNECT_FAIL;
+        for afd_events in afd_combinations() {
+            let epoll_events = sock_afd_events_to_epoll_events(afd_events);
+            let rearmed = sock_epoll_events_to_afd_events(epoll_events);
+            assert_eq!(
+                afd_events & known_afd & !rearmed,
+                0,
+                "{:#x} re-arms as {:#x}",
+                afd_events,
+                rearmed
+            );
+        }
+    }
+
+    #[test]
+    fn interests_arm_their_afd_events() {
+        let afd = |interests| sock_epoll_events_to_afd_events(interests_to_epoll(interests));
+
+        assert_ne!(afd(Interests::READABLE) & AFD_POLL_RECEIVE, 0);
+        assert_ne!(afd(Interests::WRITABLE) & AFD_POLL_SEND, 0);
+        assert_eq!(afd(Interests::READABLE) & AFD_POLL_RECEIVE_EXPEDITED, 0);
+        assert_eq!(
+            afd(Interests::PRIORITY),
+            AFD_POLL_LOCAL_CLOSE | AFD_POLL_RECEIVE_EXPEDITED
+        );
+        assert_eq!(afd(Interests::CLOSED_LOCALLY), AFD_POLL_LOCAL_CLOSE);
+    }
+
+    // The interests a user would have to ask for to be told about
+    // `epoll_events`.
+    fn epoll_to_interests(epoll_events: u32) -> Interests {
+        let mut interests = Interests::EMPTY;
+        if epoll_events & (EPOLLIN | EPOLLRDNORM) != 0 {
+            interests |= Interests::READABLE;
+        }
+        if epoll_events & (EPOLLOUT | EPOLLWRNORM | EPOLLWRBAND) != 0 {
+            interests |= Interests::WRITABLE;
+        }
+        if epoll_events & (EPOLLPRI | EPOLLRDBAND) != 0 {
+            interests |= Interests::PRIORITY;
+        }
+        if epoll_events & EPOLL_RESET != 0 {
+            interests |= Interests::CONNECTION_RESET;
+        }
+        if epoll_events & EPOLL_CLOSED_LOCALLY != 0 {
+            interests |= Interests::CLOSED_LOCALLY;
+        }
+        interests
+    }
+
+    #[test]
+    fn interests_round_trip() {
+        for bits in 0..=u8::from(Interests::ALL) {
+            let interests = match Interests::try_from(bits) {
+                Ok(interests) => interests,
+                Err(_) => continue,
+            };
+            let epoll_events = interests_to_epoll(interests);
+            let afd_events = sock_epoll_events_to_afd_events(epoll_events);
+
+            assert_eq!(epoll_to_interests(epoll_events), interests);
+            // The extension bits are added by the socket state, not by the
+            // translation which follows wepoll.
+            assert_eq!(
+                epoll_to_interests(sock_afd_events_to_epoll_events(afd_events)),
+                interests - Interests::CONNECTION_RESET - Interests::CLOSED_LOCALLY,
+                "{}",
+                interests
+            );
+
+            for single in interests {
+                assert_eq!(
+                    afd_events & sock_epoll_events_to_afd_events(interests_to_epoll(single)),
+                    sock_epoll_events_to_afd_events(interests_to_epoll(single))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn wsapoll_reports_what_afd_was_armed_for() {
+        // Every event WSAPoll reports for a socket polled for the AFD events
+        // armed for any epoll mask is one of those AFD events, so the
+        // fallback never wakes up for nothing.
+        for epoll_events in epoll_combinations() {
+            let armed = sock_epoll_events_to_afd_events(epoll_events | EPOLLERR | EPOLLHUP);
+            let requested = afd_events_to_poll_events(armed);
+            let reported = [
+                requested & POLLRDNORM,
+                requested & POLLRDBAND,
+                requested & POLLWRNORM,
+                POLLERR,
+                POLLHUP,
+                POLLNVAL,
+            ];
+            for &revent in reported.iter().filter(|&&revent| revent != 0) {
+                let afd_events = poll_events_to_afd_events(revent);
+                assert_ne!(
+                    afd_events & armed,
+                    0,
+                    "{:#x} for {:#x}",
+                    revent,
+                    epoll_events
+                );
+            }
+        }
+
+        assert_eq!(poll_events_to_afd_events(POLLNVAL), AFD_POLL_LOCAL_CLOSE);
+        assert_eq!(afd_events_to_poll_events(AFD_POLL_LOCAL_CLOSE), 0);
+    }
+}
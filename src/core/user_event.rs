@@ -0,0 +1,89 @@
+// Custom event sources in the style of kqueue's EVFILT_USER. Triggering one
+// posts a completion packet into the port, which `select` turns into an
+// ordinary `Event`. Triggers are accumulated until the packet has been
+// dequeued, so however often a user event is triggered in between, one
+// event is reported.
+
+use crate::core::port::Registration;
+use crate::core::ready::Ready;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use std::sync::Arc;
+
+/// Posts user event completions into a port, from any thread.
+pub(crate) trait Post: Send + Sync {
+    /// Posts a completion for the user event `id`.
+    fn post(&self, id: usize) -> io::Result<()>;
+}
+
+/// The state a `UserEvent` shares with its port.
+pub(crate) struct Shared {
+    id: usize,
+    readiness: AtomicU16,
+    // Whether a packet is in the port which will pick `readiness` up.
+    posted: AtomicBool,
+    poster: Arc<dyn Post>,
+}
+
+impl Shared {
+    pub(crate) fn new(id: usize, poster: Arc<dyn Post>) -> Shared {
+        Shared {
+            id,
+            readiness: AtomicU16::new(0),
+            posted: AtomicBool::new(false),
+            poster,
+        }
+    }
+
+    /// Takes the readiness accumulated since the last call, called by the
+    /// port for every packet dequeued.
+    pub(crate) fn take(&self) -> Ready {
+        // Clearing `posted` first makes any trigger racing with us post a
+        // packet of its own, so its readiness is never lost.
+        self.posted.store(false, Ordering::SeqCst);
+        Ready::from_bits(self.readiness.swap(0, Ordering::SeqCst))
+    }
+}
+
+/// A custom event source registered with a selector.
+///
+/// Triggering it, from any thread, makes the selector report an event with
+/// its token and the readiness it was triggered with. Dropping it
+/// deregisters it.
+pub struct UserEvent {
+    shared: Arc<Shared>,
+    _registration: Registration,
+}
+
+impl UserEvent {
+    pub(crate) fn new(shared: Arc<Shared>, registration: Registration) -> UserEvent {
+        UserEvent {
+            shared,
+            _registration: registration,
+        }
+    }
+
+    /// Reports `ready` with the next `select`. Triggers made before that
+    /// are merged into a single event.
+    pub fn trigger(&self, ready: Ready) -> io::Result<()> {
+        self.shared
+            .readiness
+            .fetch_or(ready.bits(), Ordering::SeqCst);
+
+        if self.shared.posted.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        self.shared.poster.post(self.shared.id).inspect_err(|_| {
+            // Nothing is in the port, the next trigger has to try again.
+            self.shared.posted.store(false, Ordering::SeqCst);
+        })
+    }
+}
+
+impl std::fmt::Debug for UserEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UserEvent")
+            .field("id", &self.shared.id)
+            .finish()
+    }
+}
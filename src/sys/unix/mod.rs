@@ -0,0 +1,8 @@
+mod poll;
+mod proactor;
+mod selector;
+mod tcp;
+
+pub use self::poll::{poll_once, SocketRef};
+pub use self::selector::{Events, Selector};
+pub use self::tcp::TcpStream;
@@ -0,0 +1,234 @@
+use crate::core::interests::Interests;
+use crate::core::ready::Ready;
+use crate::core::translate::{
+    EPOLLERR, EPOLLHUP, EPOLLIN, EPOLLOUT, EPOLLPRI, EPOLLRDHUP, EPOLL_CLOSED_LOCALLY,
+};
+use std::cmp;
+use std::io;
+use std::marker::PhantomData;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::{Duration, Instant};
+
+/// A socket borrowed for `poll_once`.
+#[derive(Debug, Clone, Copy)]
+pub struct SocketRef<'a> {
+    fd: RawFd,
+    _socket: PhantomData<&'a ()>,
+}
+
+impl<'a> SocketRef<'a> {
+    pub fn new<S: AsRawFd>(socket: &'a S) -> SocketRef<'a> {
+        SocketRef {
+            fd: socket.as_raw_fd(),
+            _socket: PhantomData,
+        }
+    }
+}
+
+impl<'a, S: AsRawFd> From<&'a S> for SocketRef<'a> {
+    fn from(socket: &'a S) -> SocketRef<'a> {
+        SocketRef::new(socket)
+    }
+}
+
+/// Waits until one of `sockets` is ready for its interests or `timeout` has
+/// elapsed, and stores the readiness of every socket next to it. Returns how
+/// many are ready, none if the timeout elapsed.
+///
+/// Errors and hang-ups are reported whatever the interests, a connect which
+/// failed as an error together with writability. A descriptor which is not
+/// open is reported as an error. Without sockets the call only waits for the
+/// timeout.
+pub fn poll_once(
+    sockets: &mut [(SocketRef<'_>, Interests, Ready)],
+    timeout: Option<Duration>,
+) -> io::Result<usize> {
+    let mut fds: Vec<libc::pollfd> = sockets
+        .iter()
+        .map(|&(socket, interests, _)| libc::pollfd {
+            fd: socket.fd,
+            events: poll_events(interests),
+            revents: 0,
+        })
+        .collect();
+
+    let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+    loop {
+        let timeout = match deadline {
+            Some(deadline) => poll_timeout(deadline.saturating_duration_since(Instant::now())),
+            None if timeout.is_some() => libc::c_int::MAX,
+            None => -1,
+        };
+        let n = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) };
+        match n {
+            -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => continue,
+            -1 => return Err(io::Error::last_os_error()),
+            _ => break,
+        }
+    }
+
+    let mut ready = 0;
+    for (&mut (_, interests, ref mut readiness), fd) in sockets.iter_mut().zip(fds.iter()) {
+        *readiness = poll_readiness(fd.revents, interests);
+        if !readiness.is_empty() {
+            ready += 1;
+        }
+    }
+    Ok(ready)
+}
+
+fn poll_events(interests: Interests) -> libc::c_short {
+    let mut events = 0;
+    if interests.is_readable() {
+        events |= libc::POLLIN | libc::POLLRDHUP;
+    }
+    if interests.is_writable() {
+        events |= libc::POLLOUT;
+    }
+    if interests.is_priority() {
+        events |= libc::POLLPRI;
+    }
+    events
+}
+
+fn poll_readiness(revents: libc::c_short, interests: Interests) -> Ready {
+    if revents & libc::POLLNVAL != 0 {
+        let closed = match interests.is_closed_locally() {
+            true => EPOLL_CLOSED_LOCALLY,
+            false => 0,
+        };
+        return Ready::ERROR | Ready::from_epoll(closed);
+    }
+
+    let bits = [
+        (libc::POLLIN, EPOLLIN),
+        (libc::POLLPRI, EPOLLPRI),
+        (libc::POLLOUT, EPOLLOUT),
+        (libc::POLLERR, EPOLLERR),
+        (libc::POLLHUP, EPOLLHUP),
+        (libc::POLLRDHUP, EPOLLRDHUP),
+    ];
+    let epoll_events = bits
+        .iter()
+        .filter(|&&(poll, _)| revents & poll != 0)
+        .fold(0, |epoll_events, &(_, epoll)| epoll_events | epoll);
+    Ready::from_epoll(epoll_events)
+}
+
+/// Converts a timeout to milliseconds, rounded up so `poll` and epoll never
+/// return before it elapsed.
+pub(super) fn poll_timeout(timeout: Duration) -> libc::c_int {
+    let millis = timeout.as_nanos().div_ceil(1_000_000);
+    cmp::min(millis, libc::c_int::MAX as u128) as libc::c_int
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::mem;
+    use std::net::{self, Shutdown, SocketAddr, TcpListener};
+
+    // Starts connecting a non-blocking socket to `addr`.
+    fn connect(addr: SocketAddr) -> io::Result<RawFd> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_INET,
+                libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                0,
+            )
+        };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let ip = match addr {
+            SocketAddr::V4(addr) => *addr.ip(),
+            SocketAddr::V6(_) => unreachable!("bound to 127.0.0.1"),
+        };
+        let mut sin: libc::sockaddr_in = unsafe { mem::zeroed() };
+        sin.sin_family = libc::AF_INET as libc::sa_family_t;
+        sin.sin_port = addr.port().to_be();
+        sin.sin_addr.s_addr = u32::from(ip).to_be();
+        let len = mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+        match unsafe { libc::connect(fd, &sin as *const _ as *const libc::sockaddr, len) } {
+            -1 if io::Error::last_os_error().raw_os_error() != Some(libc::EINPROGRESS) => {
+                let err = io::Error::last_os_error();
+                unsafe { libc::close(fd) };
+                Err(err)
+            }
+            _ => Ok(fd),
+        }
+    }
+
+    struct Fd(RawFd);
+
+    impl AsRawFd for Fd {
+        fn as_raw_fd(&self) -> RawFd {
+            self.0
+        }
+    }
+
+    impl Drop for Fd {
+        fn drop(&mut self) {
+            unsafe { libc::close(self.0) };
+        }
+    }
+
+    #[test]
+    fn sockets_report_what_they_are_ready_for() -> io::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let peer = net::TcpStream::connect(listener.local_addr()?)?;
+        let (accepted, _) = listener.accept()?;
+
+        let mut sockets = [
+            (SocketRef::new(&accepted), Interests::READABLE, Ready::EMPTY),
+            (SocketRef::new(&peer), Interests::READABLE, Ready::EMPTY),
+        ];
+        let timeout = Some(Duration::from_millis(10));
+        assert_eq!(poll_once(&mut sockets, timeout)?, 0);
+        assert!(sockets.iter().all(|&(_, _, ready)| ready.is_empty()));
+
+        (&peer).write_all(b"x")?;
+        sockets[1].1 = Interests::WRITABLE;
+        assert_eq!(poll_once(&mut sockets, None)?, 2);
+        assert_eq!(sockets[0].2, Ready::READABLE);
+        assert_eq!(sockets[1].2, Ready::WRITABLE);
+
+        peer.shutdown(Shutdown::Write)?;
+        sockets[1] = (SocketRef::new(&listener), Interests::EMPTY, Ready::EMPTY);
+        assert_eq!(poll_once(&mut sockets, Some(Duration::from_secs(0)))?, 1);
+        assert!(sockets[0].2.is_readable() && sockets[0].2.is_read_closed());
+        assert!(sockets[1].2.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn no_sockets_wait_for_the_timeout() -> io::Result<()> {
+        let start = Instant::now();
+        assert_eq!(poll_once(&mut [], Some(Duration::from_millis(20)))?, 0);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        Ok(())
+    }
+
+    #[test]
+    fn failed_connect_is_reported() -> io::Result<()> {
+        // Nothing listens on the port once the listener is gone.
+        let addr = TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+        let socket = Fd(connect(addr)?);
+
+        let mut sockets = [(SocketRef::new(&socket), Interests::WRITABLE, Ready::EMPTY)];
+        assert_eq!(poll_once(&mut sockets, Some(Duration::from_secs(5)))?, 1);
+        let ready = sockets[0].2;
+        assert!(ready.is_writable() && ready.is_error() && ready.is_write_closed());
+        Ok(())
+    }
+
+    #[test]
+    fn timeouts_are_rounded_up() {
+        assert_eq!(poll_timeout(Duration::from_secs(0)), 0);
+        assert_eq!(poll_timeout(Duration::from_nanos(1)), 1);
+        assert_eq!(poll_timeout(Duration::from_micros(1500)), 2);
+        assert_eq!(poll_timeout(Duration::MAX), libc::c_int::MAX);
+    }
+}
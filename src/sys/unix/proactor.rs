@@ -0,0 +1,169 @@
+use crate::core::proactor::{IoCompletion, IoKind, IoRequest};
+use std::collections::VecDeque;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::thread;
+
+/// Threads running the reads and writes of one selector.
+const POOL_THREADS: usize = 4;
+
+struct Job {
+    fd: RawFd,
+    request: IoRequest,
+}
+
+#[derive(Default)]
+struct Jobs {
+    queue: VecDeque<Job>,
+    shutdown: bool,
+}
+
+struct Shared {
+    jobs: Mutex<Jobs>,
+    available: Condvar,
+    done: Mutex<VecDeque<IoCompletion>>,
+    // Readable while completions are waiting in `done`.
+    wake: RawFd,
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.wake) };
+    }
+}
+
+/// Emulates completion based I/O with blocking calls on a pool of threads,
+/// which announce finished operations through an eventfd.
+pub(crate) struct Pool {
+    shared: Arc<Shared>,
+}
+
+impl Pool {
+    pub(crate) fn new() -> io::Result<Pool> {
+        let wake = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+        if wake == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let pool = Pool {
+            shared: Arc::new(Shared {
+                jobs: Mutex::new(Jobs::default()),
+                available: Condvar::new(),
+                done: Mutex::new(VecDeque::new()),
+                wake,
+            }),
+        };
+        for _ in 0..POOL_THREADS {
+            let shared = pool.shared.clone();
+            // Threads spawned so far stop when the pool is dropped.
+            thread::Builder::new().spawn(move || work(&shared))?;
+        }
+        Ok(pool)
+    }
+
+    /// Returns the eventfd which is readable while completions are waiting.
+    pub(crate) fn wake_fd(&self) -> RawFd {
+        self.shared.wake
+    }
+
+    pub(crate) fn submit(&self, fd: RawFd, request: IoRequest) {
+        let mut jobs = lock(&self.shared.jobs);
+        jobs.queue.push_back(Job { fd, request });
+        self.shared.available.notify_one();
+    }
+
+    /// Takes up to `max` completions. The eventfd stays readable while some
+    /// are left.
+    pub(crate) fn take(&self, max: usize) -> Vec<IoCompletion> {
+        let mut done = lock(&self.shared.done);
+        let n = done.len().min(max);
+        let taken = done.drain(..n).collect();
+
+        // Workers push under the lock before they signal, so nothing pushed
+        // after this point is missed.
+        if done.is_empty() {
+            let mut count = 0u64;
+            unsafe { libc::read(self.shared.wake, &mut count as *mut u64 as *mut _, 8) };
+        }
+        taken
+    }
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        // Operations still queued are dropped with their buffers. Those
+        // running may block for good, like a read from a pipe nobody writes
+        // to, so they are not waited for. Their threads drop the outcome and
+        // stop once the call returns.
+        let mut jobs = lock(&self.shared.jobs);
+        jobs.shutdown = true;
+        jobs.queue.clear();
+        self.shared.available.notify_all();
+    }
+}
+
+fn work(shared: &Shared) {
+    loop {
+        let job = {
+            let mut jobs = lock(&shared.jobs);
+            loop {
+                if jobs.shutdown {
+                    return;
+                }
+                match jobs.queue.pop_front() {
+                    Some(job) => break job,
+                    None => {
+                        jobs = shared
+                            .available
+                            .wait(jobs)
+                            .unwrap_or_else(PoisonError::into_inner)
+                    }
+                }
+            }
+        };
+
+        let mut request = job.request;
+        let result = transfer(job.fd, &mut request);
+        if lock(&shared.jobs).shutdown {
+            return;
+        }
+        lock(&shared.done).push_back(request.complete(result));
+        let one = 1u64;
+        unsafe { libc::write(shared.wake, &one as *const u64 as *const _, 8) };
+    }
+}
+
+/// Reads or writes at the offset of the request, handles without a position
+/// like pipes and sockets transfer at their current position instead.
+fn transfer(fd: RawFd, request: &mut IoRequest) -> io::Result<usize> {
+    let offset = request.offset as libc::off_t;
+    let buf = request.buf.as_mut_ptr() as *mut libc::c_void;
+    let len = request.buf.len();
+
+    loop {
+        let n = match request.kind {
+            IoKind::Read => unsafe { libc::pread(fd, buf, len, offset) },
+            IoKind::Write => unsafe { libc::pwrite(fd, buf, len, offset) },
+        };
+        let n = match n {
+            -1 if io::Error::last_os_error().raw_os_error() == Some(libc::ESPIPE) => {
+                match request.kind {
+                    IoKind::Read => unsafe { libc::read(fd, buf, len) },
+                    IoKind::Write => unsafe { libc::write(fd, buf, len) },
+                }
+            }
+            n => n,
+        };
+
+        match n {
+            -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => continue,
+            -1 => return Err(io::Error::last_os_error()),
+            n => return Ok(n as usize),
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
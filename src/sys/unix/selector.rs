@@ -0,0 +1,745 @@
+use super::poll::poll_timeout;
+use super::proactor::Pool;
+use super::tcp::TcpStream;
+use crate::core::backend::Backend;
+use crate::core::builder::{SelectorBuilder, Trigger};
+use crate::core::event::{self, Event, RawEvent};
+use crate::core::interests::Interests;
+use crate::core::proactor::{not_associated, IoBuf, IoHandle, IoKind, IoRequest, SelectorId};
+use crate::core::token::Token;
+use crate::core::translate::{interests_to_epoll, EPOLL_EXTENSIONS};
+use std::io;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The token of the epoll event announcing I/O completions, which
+/// registrations cannot use.
+const POOL_TOKEN: Token = Token(usize::MAX);
+
+/// The native backend, which hands everything to epoll.
+pub struct Selector {
+    ep: RawFd,
+    //epoll flags added to the events of every registration
+    trigger_events: u32,
+    //runs completion based I/O, started by the first association
+    pool: Option<Pool>,
+    //stamped on the handles associated with the selector
+    id: SelectorId,
+}
+
+impl Selector {
+    pub fn new() -> io::Result<Selector> {
+        SelectorBuilder::default().build()
+    }
+
+    /// Returns the mechanism the selector waits with, always `Epoll`.
+    pub fn backend(&self) -> Backend {
+        Backend::Epoll
+    }
+
+    pub fn select(&mut self, events: &mut Events, timeout: Option<Duration>) -> io::Result<()> {
+        match timeout.map(|timeout| Instant::now().checked_add(timeout)) {
+            Some(Some(deadline)) => self.select_until(events, deadline),
+            // Too far away to tell apart from forever.
+            Some(None) | None => {
+                events.reset();
+                let n = self.wait(events, -1)?;
+                self.dispatch(events, n);
+                Ok(())
+            }
+        }
+    }
+
+    /// Waits for events until `deadline`. Epoll counts in milliseconds, so
+    /// the wait may last up to a millisecond longer. A deadline which has
+    /// passed only takes the events already there.
+    pub fn select_until(&mut self, events: &mut Events, deadline: Instant) -> io::Result<()> {
+        events.reset();
+        if events.statuses_mut().is_empty() {
+            // Events held back by the last call fill the buffer already.
+            return Ok(());
+        }
+
+        let n = wait_until(deadline, Instant::now, |timeout| self.wait(events, timeout))?;
+        self.dispatch(events, n);
+        Ok(())
+    }
+
+    fn wait(&mut self, events: &mut Events, timeout: libc::c_int) -> io::Result<usize> {
+        let statuses = events.statuses_mut();
+        if statuses.is_empty() {
+            return Ok(0);
+        }
+
+        let n = unsafe {
+            libc::epoll_wait(
+                self.ep,
+                statuses.as_mut_ptr(),
+                statuses.len() as libc::c_int,
+                timeout,
+            )
+        };
+
+        match n {
+            -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => Ok(0),
+            -1 => Err(io::Error::last_os_error()),
+            n => Ok(n as usize),
+        }
+    }
+
+    fn dispatch(&mut self, events: &mut Events, n: usize) {
+        let mut completed = false;
+        for i in 0..n {
+            let status = events.statuses()[i];
+            let token = Token::from(status.u64 as usize);
+            if token == POOL_TOKEN {
+                completed = true;
+                continue;
+            }
+            events.push_event(Event::from_raw(token, status.events, None));
+        }
+
+        // Completions only get the room the events left.
+        if completed {
+            let room = events.capacity() - events.len() - events.completions().len();
+            let pool = self.pool.as_ref().unwrap();
+            for completion in pool.take(room) {
+                events.push_completion(completion);
+            }
+        }
+    }
+
+    /// Registers `sock`. `Token(usize::MAX)` is reserved for the selector
+    /// and rejected.
+    pub fn register(
+        &mut self,
+        sock: &mut TcpStream,
+        token: Token,
+        interests: Interests,
+    ) -> io::Result<()> {
+        let mut event = self.epoll_event(token, interests)?;
+        self.ctl(libc::EPOLL_CTL_ADD, sock.as_raw_fd(), &mut event)
+    }
+
+    /// Changes the token and interests of a registered stream.
+    pub fn reregister(
+        &mut self,
+        sock: &mut TcpStream,
+        token: Token,
+        interests: Interests,
+    ) -> io::Result<()> {
+        let mut event = self.epoll_event(token, interests)?;
+        self.ctl(libc::EPOLL_CTL_MOD, sock.as_raw_fd(), &mut event)
+    }
+
+    /// Deregisters a stream, which is done as well when it is dropped.
+    pub fn deregister(&mut self, sock: &mut TcpStream) -> io::Result<()> {
+        let mut event = libc::epoll_event::zeroed();
+        self.ctl(libc::EPOLL_CTL_DEL, sock.as_raw_fd(), &mut event)
+    }
+
+    fn epoll_event(&self, token: Token, interests: Interests) -> io::Result<libc::epoll_event> {
+        if token == POOL_TOKEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "token is reserved for the selector",
+            ));
+        }
+
+        // The kernel has no notion of the AFD only events.
+        let epoll_events = interests_to_epoll(interests) & !EPOLL_EXTENSIONS;
+        Ok(libc::epoll_event {
+            events: epoll_events | self.trigger_events | libc::EPOLLRDHUP as u32,
+            u64: usize::from(token) as u64,
+        })
+    }
+
+    fn ctl(&self, op: libc::c_int, fd: RawFd, event: &mut libc::epoll_event) -> io::Result<()> {
+        match unsafe { libc::epoll_ctl(self.ep, op, fd, event as *mut _) } {
+            -1 => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Associates `handle` for completion based I/O, its completions are
+    /// reported with `token`. The operations run on a pool of threads, so
+    /// `handle` should be in blocking mode.
+    ///
+    /// They work on a duplicate of `handle`, which may be closed while they
+    /// are in flight without them hitting whatever reuses its descriptor.
+    pub fn associate<H: AsRawFd>(&mut self, handle: &H, token: Token) -> io::Result<IoHandle> {
+        let fd = unsafe { libc::fcntl(handle.as_raw_fd(), libc::F_DUPFD_CLOEXEC, 0) };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        if self.pool.is_none() {
+            let pool = Pool::new()?;
+            // Level triggered, the eventfd stays readable while completions
+            // which did not fit into the events are waiting.
+            let mut event = libc::epoll_event {
+                events: libc::EPOLLIN as u32,
+                u64: usize::from(POOL_TOKEN) as u64,
+            };
+            self.ctl(libc::EPOLL_CTL_ADD, pool.wake_fd(), &mut event)?;
+            self.pool = Some(pool);
+        }
+
+        let handle = IoHandle::new(fd.as_raw_fd() as usize, token, self.id);
+        Ok(handle.owned_by(Arc::new(fd)))
+    }
+
+    /// Starts reading into `buf` at `offset` of an associated handle. If it
+    /// cannot be started the buffer is dropped.
+    pub fn submit_read<B: Into<IoBuf>>(
+        &mut self,
+        handle: &IoHandle,
+        buf: B,
+        offset: u64,
+    ) -> io::Result<()> {
+        self.submit(handle, IoKind::Read, buf.into(), offset)
+    }
+
+    /// Starts writing `buf` at `offset` of an associated handle. If it
+    /// cannot be started the buffer is dropped.
+    pub fn submit_write<B: Into<IoBuf>>(
+        &mut self,
+        handle: &IoHandle,
+        buf: B,
+        offset: u64,
+    ) -> io::Result<()> {
+        self.submit(handle, IoKind::Write, buf.into(), offset)
+    }
+
+    fn submit(
+        &mut self,
+        handle: &IoHandle,
+        kind: IoKind,
+        buf: IoBuf,
+        offset: u64,
+    ) -> io::Result<()> {
+        if handle.selector != self.id {
+            return Err(not_associated());
+        }
+        // Associating started the pool.
+        let pool = self.pool.as_ref().unwrap();
+        let request = IoRequest::new(handle, kind, buf, offset);
+        pool.submit(handle.handle as RawFd, request);
+        Ok(())
+    }
+}
+
+/// Waits with `wait` until it dequeues something or `deadline` has passed by
+/// `now`, returns how much was dequeued. The time left is rounded up to
+/// milliseconds, waits only end early if interrupted.
+fn wait_until<N, W>(deadline: Instant, now: N, mut wait: W) -> io::Result<usize>
+where
+    N: Fn() -> Instant,
+    W: FnMut(libc::c_int) -> io::Result<usize>,
+{
+    loop {
+        let n = wait(poll_timeout(deadline.saturating_duration_since(now())))?;
+        if n > 0 || now() >= deadline {
+            return Ok(n);
+        }
+    }
+}
+
+impl std::fmt::Debug for Selector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Selector")
+            .field("ep", &self.ep)
+            .field("trigger_events", &self.trigger_events)
+            .finish()
+    }
+}
+
+impl SelectorBuilder {
+    /// Creates a selector with this configuration.
+    pub fn build(&self) -> io::Result<Selector> {
+        self.required_backend(&[Backend::Epoll])?;
+        self.validate(Backend::Epoll)?;
+
+        let trigger_events = match self.trigger_for(Backend::Epoll) {
+            Trigger::Level => 0,
+            Trigger::Edge => libc::EPOLLET as u32,
+            Trigger::Oneshot => libc::EPOLLONESHOT as u32,
+        };
+
+        let ep = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+
+        match ep {
+            -1 => Err(io::Error::last_os_error()),
+            _ => Ok(Selector {
+                ep,
+                trigger_events,
+                pool: None,
+                id: SelectorId::new(),
+            }),
+        }
+    }
+
+    /// Reports whether AFD polling is usable, which it never is outside of
+    /// Windows.
+    pub fn probe(&self) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "AFD polling is only available on Windows",
+        ))
+    }
+}
+
+impl Drop for Selector {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.ep) };
+    }
+}
+
+impl RawEvent for libc::epoll_event {
+    fn zeroed() -> libc::epoll_event {
+        libc::epoll_event { events: 0, u64: 0 }
+    }
+}
+
+pub type Events = event::Events<libc::epoll_event>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::buffer_pool::BufferPoolBuilder;
+    use crate::core::driver::sim::SimClock;
+    use crate::core::proactor::IoCompletion;
+    use crate::core::translate;
+    use std::cell::RefCell;
+    use std::io::Write;
+    use std::net::{self, TcpListener};
+    use std::os::unix::io::FromRawFd;
+
+    #[test]
+    fn registered_stream_reports_readiness() -> io::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let mut peer = net::TcpStream::connect(listener.local_addr()?)?;
+        let (accepted, _) = listener.accept()?;
+        accepted.set_nonblocking(true)?;
+
+        let mut selector = Selector::new()?;
+        let mut events = Events::with_capacity(16);
+        let mut stream = TcpStream::from_std(accepted);
+        selector.register(
+            &mut stream,
+            Token(7),
+            Interests::READABLE | Interests::WRITABLE,
+        )?;
+
+        selector.select(&mut events, Some(Duration::from_secs(1)))?;
+        assert_eq!(events.len(), 1);
+        let ev = events.get(0).unwrap();
+        assert_eq!(ev.token(), Token(7));
+        assert_eq!(ev.raw_afd_events(), None);
+        assert!(ev.is_writable());
+        assert!(!ev.is_readable());
+
+        peer.write_all(b"ping")?;
+        selector.select(&mut events, Some(Duration::from_secs(1)))?;
+        assert_eq!(events.len(), 1);
+        assert!(events.get(0).unwrap().is_readable());
+
+        Ok(())
+    }
+
+    #[test]
+    fn streams_are_reregistered_and_deregistered() -> io::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let _peer = net::TcpStream::connect(listener.local_addr()?)?;
+        let mut stream = TcpStream::from_std(listener.accept()?.0);
+
+        let mut selector = Selector::new()?;
+        let mut events = Events::with_capacity(8);
+        let not_found =
+            |result: io::Result<()>| result.unwrap_err().kind() == io::ErrorKind::NotFound;
+        assert!(not_found(selector.reregister(
+            &mut stream,
+            Token(1),
+            Interests::WRITABLE
+        )));
+
+        selector.register(&mut stream, Token(1), Interests::READABLE)?;
+        selector.select(&mut events, Some(Duration::from_millis(10)))?;
+        assert!(events.is_empty());
+        selector.reregister(&mut stream, Token(2), Interests::WRITABLE)?;
+        selector.select(&mut events, Some(Duration::from_secs(1)))?;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events.get(0).unwrap().token(), Token(2));
+
+        // The registration stays with the stream if it is not ours.
+        assert!(not_found(Selector::new()?.deregister(&mut stream)));
+        selector.deregister(&mut stream)?;
+        assert!(not_found(selector.deregister(&mut stream)));
+        assert!(not_found(selector.reregister(
+            &mut stream,
+            Token(2),
+            Interests::WRITABLE
+        )));
+
+        selector.register(&mut stream, Token(3), Interests::WRITABLE)?;
+        selector.select(&mut events, Some(Duration::from_secs(1)))?;
+        let tokens: Vec<Token> = events.iter().map(|event| event.token()).collect();
+        assert_eq!(tokens, [Token(3)]);
+        Ok(())
+    }
+
+    #[test]
+    fn reserved_token_is_rejected() -> io::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let _peer = net::TcpStream::connect(listener.local_addr()?)?;
+        let mut stream = TcpStream::from_std(listener.accept()?.0);
+
+        let mut selector = Selector::new()?;
+        let (_reader, writer) = pipe();
+        selector.associate(&writer, Token(1))?;
+        let reserved = selector.register(&mut stream, Token(usize::MAX), Interests::WRITABLE);
+        assert_eq!(reserved.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        selector.register(&mut stream, Token(usize::MAX - 1), Interests::WRITABLE)?;
+        let mut events = Events::with_capacity(4);
+        selector.select(&mut events, Some(Duration::from_secs(1)))?;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events.get(0).unwrap().token(), Token(usize::MAX - 1));
+        Ok(())
+    }
+
+    #[test]
+    fn builder_creates_an_epoll_selector() -> io::Result<()> {
+        let builder = SelectorBuilder::new().afd_device("\\Device\\Afd\\Other");
+        assert_eq!(builder.build()?.backend(), Backend::Epoll);
+        assert_eq!(
+            builder.probe().unwrap_err().kind(),
+            io::ErrorKind::Unsupported
+        );
+        Ok(())
+    }
+
+    /// Runs `wait_until` on `clock`, the waits dequeue `dequeued` each.
+    fn wait_until_on(clock: &RefCell<SimClock>, deadline: Instant, dequeued: usize) -> usize {
+        let wait = |timeout: libc::c_int| {
+            let timeout = Duration::from_millis(timeout as u64);
+            clock.borrow_mut().wait(Some(timeout), dequeued == 0);
+            Ok(dequeued)
+        };
+        wait_until(deadline, || clock.borrow().now(), wait).unwrap()
+    }
+
+    #[test]
+    fn select_until_keeps_the_deadline() {
+        let ms = Duration::from_millis;
+        let clock = RefCell::new(SimClock::default());
+        let start = clock.borrow().now();
+
+        // Rounded up, a single wait does unless it is interrupted.
+        assert_eq!(wait_until_on(&clock, start + ms(2), 0), 0);
+        clock.borrow_mut().early_returns = vec![ms(1)].into();
+        let deadline = start + ms(2) + Duration::from_micros(2500);
+        assert_eq!(wait_until_on(&clock, deadline, 0), 0);
+        assert_eq!(clock.borrow().now(), start + ms(5));
+        let waits = [ms(2), ms(3), ms(2)];
+        assert_eq!(clock.borrow().waits, waits.map(Some));
+
+        // Events end the wait, a deadline which has passed does not block.
+        clock.borrow_mut().waits.clear();
+        assert_eq!(wait_until_on(&clock, start + ms(10), 2), 2);
+        assert_eq!(wait_until_on(&clock, start, 0), 0);
+        assert_eq!(clock.borrow().now(), start + ms(5));
+        assert_eq!(clock.borrow().waits, [Some(ms(5)), Some(ms(0))]);
+    }
+
+    #[test]
+    fn builder_options_are_checked() {
+        let builders = [
+            SelectorBuilder::new().backend(Backend::Afd),
+            SelectorBuilder::new().concurrency(0),
+            SelectorBuilder::new().group_size(0),
+        ];
+        for builder in builders.iter() {
+            assert!(builder.build().is_err(), "{:?}", builder);
+        }
+    }
+
+    #[test]
+    fn level_trigger_reports_readiness_again() -> io::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let _peer = net::TcpStream::connect(listener.local_addr()?)?;
+        let (accepted, _) = listener.accept()?;
+
+        for &(trigger, again) in [(Trigger::Edge, false), (Trigger::Level, true)].iter() {
+            let mut selector = SelectorBuilder::new().trigger(trigger).build()?;
+            let mut events = Events::with_capacity(16);
+            let mut stream = TcpStream::from_std(accepted.try_clone()?);
+            selector.register(&mut stream, Token(1), Interests::WRITABLE)?;
+
+            selector.select(&mut events, Some(Duration::from_secs(1)))?;
+            assert_eq!(events.len(), 1);
+            selector.select(&mut events, Some(Duration::from_millis(0)))?;
+            assert_eq!(events.len() == 1, again, "{:?}", trigger);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn epoll_constants_match_the_kernel() {
+        let pairs = [
+            (translate::EPOLLIN, libc::EPOLLIN),
+            (translate::EPOLLPRI, libc::EPOLLPRI),
+            (translate::EPOLLOUT, libc::EPOLLOUT),
+            (translate::EPOLLERR, libc::EPOLLERR),
+            (translate::EPOLLHUP, libc::EPOLLHUP),
+            (translate::EPOLLRDNORM, libc::EPOLLRDNORM),
+            (translate::EPOLLRDBAND, libc::EPOLLRDBAND),
+            (translate::EPOLLWRNORM, libc::EPOLLWRNORM),
+            (translate::EPOLLWRBAND, libc::EPOLLWRBAND),
+            (translate::EPOLLMSG, libc::EPOLLMSG),
+            (translate::EPOLLRDHUP, libc::EPOLLRDHUP),
+        ];
+
+        for &(ours, kernel) in pairs.iter() {
+            assert_eq!(ours, kernel as u32);
+        }
+    }
+
+    #[test]
+    fn peer_shutdown_reports_read_closed() -> io::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let peer = net::TcpStream::connect(listener.local_addr()?)?;
+        let (accepted, _) = listener.accept()?;
+        accepted.set_nonblocking(true)?;
+
+        let mut selector = Selector::new()?;
+        let mut events = Events::with_capacity(16);
+        let mut stream = TcpStream::from_std(accepted);
+        selector.register(&mut stream, Token(3), Interests::READABLE)?;
+
+        peer.shutdown(net::Shutdown::Write)?;
+        selector.select(&mut events, Some(Duration::from_secs(1)))?;
+        assert_eq!(events.len(), 1);
+        assert!(events.get(0).unwrap().is_read_closed());
+
+        Ok(())
+    }
+
+    #[test]
+    fn urgent_data_reports_priority() -> io::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let peer = TcpStream::from_std(net::TcpStream::connect(listener.local_addr()?)?);
+        let (accepted, _) = listener.accept()?;
+        accepted.set_nonblocking(true)?;
+
+        let mut selector = Selector::new()?;
+        let mut events = Events::with_capacity(16);
+        let mut stream = TcpStream::from_std(accepted);
+        selector.register(&mut stream, Token(5), Interests::PRIORITY)?;
+
+        assert_eq!(peer.send_oob(b"!")?, 1);
+        selector.select(&mut events, Some(Duration::from_secs(1)))?;
+        assert_eq!(events.len(), 1);
+        let ev = events.get(0).unwrap();
+        assert!(ev.is_priority());
+        assert!(!ev.is_readable());
+
+        let mut buf = [0; 1];
+        assert_eq!(stream.recv_oob(&mut buf)?, 1);
+        assert_eq!(&buf, b"!");
+
+        Ok(())
+    }
+
+    #[test]
+    fn readiness_beyond_capacity_is_reported_next_time() -> io::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let mut selector = Selector::new()?;
+        let mut events = Events::with_capacity(1);
+        let mut streams = Vec::new();
+
+        for token in 0..3 {
+            let _peer = net::TcpStream::connect(listener.local_addr()?)?;
+            let (accepted, _) = listener.accept()?;
+            let mut stream = TcpStream::from_std(accepted);
+            selector.register(&mut stream, Token(token), Interests::WRITABLE)?;
+            streams.push(stream);
+        }
+
+        let mut tokens = Vec::new();
+        for _ in 0..3 {
+            selector.select(&mut events, Some(Duration::from_secs(1)))?;
+            assert_eq!(events.len(), 1);
+            tokens.extend(events.iter().map(|e| e.token()));
+        }
+        tokens.sort();
+        assert_eq!(tokens, [Token(0), Token(1), Token(2)]);
+
+        Ok(())
+    }
+
+    fn pipe() -> (std::fs::File, std::fs::File) {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        unsafe {
+            (
+                std::fs::File::from_raw_fd(fds[0]),
+                std::fs::File::from_raw_fd(fds[1]),
+            )
+        }
+    }
+
+    /// Selects until `n` I/O completions have been reported.
+    fn completions(selector: &mut Selector, n: usize) -> io::Result<Vec<IoCompletion>> {
+        let mut events = Events::with_capacity(16);
+        let mut completions = Vec::new();
+        while completions.len() < n {
+            selector.select(&mut events, Some(Duration::from_secs(5)))?;
+            assert!(events.is_empty());
+            completions.extend(events.drain_completions());
+        }
+        Ok(completions)
+    }
+
+    #[test]
+    fn file_reads_and_writes_complete() -> io::Result<()> {
+        let path = std::env::temp_dir().join(format!("proactor-{}", std::process::id()));
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        std::fs::remove_file(&path)?;
+
+        let mut selector = Selector::new()?;
+        let handle = selector.associate(&file, Token(4))?;
+        selector.submit_write(&handle, b"hello world".to_vec(), 0)?;
+        let written = completions(&mut selector, 1)?;
+        assert_eq!(written[0].token(), Token(4));
+        assert_eq!(written[0].kind(), IoKind::Write);
+        assert_eq!(written[0].result().unwrap(), 11);
+
+        selector.submit_read(&handle, vec![0; 5], 6)?;
+        selector.submit_read(&handle, vec![0; 5], 11)?;
+        let mut read = completions(&mut selector, 2)?;
+        read.sort_by_key(|completion| completion.result().unwrap());
+        // Reading at the end of the file transfers nothing.
+        assert_eq!(read[0].result().unwrap(), 0);
+        let (buf, n) = read.pop().unwrap().into_parts();
+        assert_eq!(&buf[..n?], b"world");
+        Ok(())
+    }
+
+    #[test]
+    fn completions_beyond_capacity_are_reported_next_time() -> io::Result<()> {
+        let (reader, mut writer) = pipe();
+
+        let mut selector = Selector::new()?;
+        let handle = selector.associate(&reader, Token(8))?;
+        for _ in 0..3 {
+            selector.submit_read(&handle, vec![0; 1], 0)?;
+        }
+        writer.write_all(b"abc")?;
+
+        // Waits until reads have completed before a stream is ready too, so
+        // that the completions are announced first.
+        let mut wake = libc::pollfd {
+            fd: selector.pool.as_ref().unwrap().wake_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        assert_eq!(unsafe { libc::poll(&mut wake, 1, 5000) }, 1);
+        std::thread::sleep(Duration::from_millis(50));
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let _peer = net::TcpStream::connect(listener.local_addr()?)?;
+        let mut stream = TcpStream::from_std(listener.accept()?.0);
+        selector.register(&mut stream, Token(1), Interests::WRITABLE)?;
+
+        let mut events = Events::with_capacity(2);
+        let mut read = Vec::new();
+        let mut ready = Vec::new();
+        while read.len() < 3 {
+            selector.select(&mut events, Some(Duration::from_secs(5)))?;
+            assert!(events.len() + events.completions().len() <= 2);
+            ready.extend(events.iter().map(|e| e.token()));
+            read.extend(
+                events
+                    .drain_completions()
+                    .map(|completion| completion.buf()[0]),
+            );
+        }
+        read.sort();
+        assert_eq!(read, b"abc");
+        assert_eq!(ready, [Token(1)]);
+
+        // Another selector refuses the handle, whether it runs a pool or not.
+        let mut other = Selector::new()?;
+        let foreign = other.submit_read(&handle, vec![0; 1], 0);
+        assert_eq!(foreign.unwrap_err().kind(), io::ErrorKind::NotFound);
+        other.associate(&writer, Token(9))?;
+        let foreign = other.submit_read(&handle, vec![0; 1], 0);
+        assert_eq!(foreign.unwrap_err().kind(), io::ErrorKind::NotFound);
+        Ok(())
+    }
+
+    #[test]
+    fn operations_outlive_the_associated_file() -> io::Result<()> {
+        let (reader, mut writer) = pipe();
+
+        let mut selector = Selector::new()?;
+        let handle = selector.associate(&reader, Token(2))?;
+        selector.submit_read(&handle, vec![0; 1], 0)?;
+        // Whatever reuses the descriptor is left alone.
+        drop(reader);
+        let (reused, _reused_writer) = pipe();
+        selector.submit_read(&handle, vec![0; 1], 0)?;
+        drop(handle);
+
+        writer.write_all(b"ab")?;
+        let mut read: Vec<u8> = completions(&mut selector, 2)?
+            .iter()
+            .map(|completion| completion.buf()[0])
+            .collect();
+        read.sort();
+        assert_eq!(read, b"ab");
+        drop(reused);
+        Ok(())
+    }
+
+    #[test]
+    fn pooled_buffers_go_back_when_operations_are_dropped() -> io::Result<()> {
+        let (reader, mut writer) = pipe();
+        let pool = BufferPoolBuilder::new()
+            .buffer_size(1)
+            .max_outstanding(8)
+            .build()?;
+
+        let mut selector = Selector::new()?;
+        let handle = selector.associate(&reader, Token(1))?;
+        for _ in 0..8 {
+            selector.submit_read(&handle, pool.acquire()?, 0)?;
+        }
+        assert!(pool.acquire().is_err());
+        writer.write_all(b"a")?;
+        let read = completions(&mut selector, 1)?;
+        assert_eq!(read[0].buf(), b"a");
+        drop(read);
+        assert_eq!(pool.stats().outstanding, 7);
+
+        // The reads which never started are dropped with the selector, those
+        // blocked in the pipe once they return.
+        drop(selector);
+        writer.write_all(&[0; 8])?;
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while pool.stats().outstanding > 0 {
+            assert!(std::time::Instant::now() < deadline, "{:?}", pool);
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(pool.stats().allocated, 8);
+        Ok(())
+    }
+}
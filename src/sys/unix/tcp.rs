@@ -0,0 +1,108 @@
+use super::poll::{poll_once, SocketRef};
+use crate::core::interests::Interests;
+use crate::core::ready::Ready;
+use std::io::{self, Read, Write};
+use std::net;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Duration;
+
+#[derive(Debug)]
+pub struct TcpStream {
+    sock: net::TcpStream,
+}
+
+impl TcpStream {
+    pub fn from_std(socket: net::TcpStream) -> TcpStream {
+        TcpStream { sock: socket }
+    }
+
+    /// Waits until the stream is ready for `interests`, without a selector,
+    /// and returns its readiness, which is empty if `timeout` elapsed first.
+    /// Errors and hang-ups are reported whatever the interests.
+    pub fn wait(&self, interests: Interests, timeout: Option<Duration>) -> io::Result<Ready> {
+        let mut socket = [(SocketRef::new(self), interests, Ready::EMPTY)];
+        poll_once(&mut socket, timeout)?;
+        Ok(socket[0].2)
+    }
+
+    /// Receives out-of-band data, which is announced by priority readiness.
+    pub fn recv_oob(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = unsafe {
+            libc::recv(
+                self.sock.as_raw_fd(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+                libc::MSG_OOB,
+            )
+        };
+        if n < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(n as usize)
+        }
+    }
+
+    /// Sends `buf` as out-of-band data. TCP only marks the last byte as
+    /// urgent, so peers usually send a single byte at a time.
+    pub fn send_oob(&self, buf: &[u8]) -> io::Result<usize> {
+        let n = unsafe {
+            libc::send(
+                self.sock.as_raw_fd(),
+                buf.as_ptr() as *const libc::c_void,
+                buf.len(),
+                libc::MSG_OOB | libc::MSG_NOSIGNAL,
+            )
+        };
+        if n < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(n as usize)
+        }
+    }
+}
+
+impl Read for TcpStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.sock.read(buf)
+    }
+}
+
+impl Write for TcpStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.sock.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sock.flush()
+    }
+}
+
+impl AsRawFd for TcpStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.sock.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn wait_returns_once_ready() -> io::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let mut peer = net::TcpStream::connect(listener.local_addr()?)?;
+        let stream = TcpStream::from_std(listener.accept()?.0);
+
+        let timeout = Some(Duration::from_millis(10));
+        assert!(stream.wait(Interests::READABLE, timeout)?.is_empty());
+        assert!(stream.wait(Interests::WRITABLE, None)?.is_writable());
+
+        peer.write_all(b"x")?;
+        assert_eq!(stream.wait(Interests::READABLE, None)?, Ready::READABLE);
+        drop(peer);
+        let ready = stream.wait(Interests::READABLE, timeout)?;
+        assert!(ready.is_readable() && ready.is_read_closed());
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+use crate::core::afd::{self, AfdPollInfoBuf, AFD_POLL_INFO, OBJECT_ATTRIBUTES, UNICODE_STRING};
+use crate::core::backend::AfdDevice;
+use crate::core::key::Key;
+use ntapi::ntioapi::{
+    IO_STATUS_BLOCK_u, NtCreateFile, NtDeviceIoControlFile, FILE_OPEN, IO_STATUS_BLOCK,
+};
+use ntapi::ntrtl::RtlNtStatusToDosError;
+use std::io;
+use std::mem::size_of;
+use widestring::U16CString;
+use winapi::shared::minwindef::ULONG;
+use winapi::shared::ntdef::{NULL, PHANDLE, PVOID};
+use winapi::shared::ntstatus::{STATUS_PENDING, STATUS_SUCCESS};
+use winapi::shared::winerror::ERROR_IO_PENDING;
+use winapi::um::handleapi::CloseHandle;
+use winapi::um::ioapiset::CreateIoCompletionPort;
+use winapi::um::minwinbase::OVERLAPPED;
+use winapi::um::winbase::{SetFileCompletionNotificationModes, FILE_SKIP_SET_EVENT_ON_HANDLE};
+use winapi::um::winnt::{FILE_SHARE_READ, FILE_SHARE_WRITE, HANDLE, SYNCHRONIZE};
+
+const IOCTL_AFD_POLL: ULONG = 0x00012024;
+
+pub(crate) fn afd_poll(
+    afd_helper_handle: HANDLE,
+    poll_info: &mut AFD_POLL_INFO,
+    overlapped: &mut OVERLAPPED,
+) -> io::Result<()> {
+    let size = size_of::<AFD_POLL_INFO>();
+    afd_ioctl_poll(afd_helper_handle, poll_info, size, overlapped)
+}
+
+/// Polls every handle of `poll_info` at once.
+pub(crate) fn afd_poll_many(
+    afd_helper_handle: HANDLE,
+    poll_info: &mut AfdPollInfoBuf,
+    overlapped: &mut OVERLAPPED,
+) -> io::Result<()> {
+    let size = poll_info.size();
+    afd_ioctl_poll(afd_helper_handle, poll_info.as_mut_ptr(), size, overlapped)
+}
+
+fn afd_ioctl_poll(
+    afd_helper_handle: HANDLE,
+    poll_info: *mut AFD_POLL_INFO,
+    size: usize,
+    overlapped: &mut OVERLAPPED,
+) -> io::Result<()> {
+    let piosb = &mut overlapped.Internal as *mut _ as *mut IO_STATUS_BLOCK;
+
+    let status = unsafe {
+        (*piosb).u.Status = STATUS_PENDING;
+
+        NtDeviceIoControlFile(
+            afd_helper_handle,
+            overlapped.hEvent,
+            None,
+            &mut *overlapped as *mut _ as PVOID,
+            piosb,
+            IOCTL_AFD_POLL,
+            poll_info as PVOID,
+            size as u32,
+            poll_info as PVOID,
+            size as u32,
+        )
+    };
+
+    match status {
+        STATUS_SUCCESS => Ok(()),
+        STATUS_PENDING => Err(io::Error::from_raw_os_error(ERROR_IO_PENDING as _)),
+        _ => unsafe {
+            Err(io::Error::from_raw_os_error(
+                RtlNtStatusToDosError(status) as _
+            ))
+        },
+    }
+}
+
+/// Opens the AFD device at the NT path `path`.
+pub(crate) fn afd_open(path: &str) -> io::Result<HANDLE> {
+    let name = U16CString::from_str(path)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "AFD device path contains nul"))?;
+    let name = UNICODE_STRING::new(name.as_slice_with_nul());
+    let attributes = OBJECT_ATTRIBUTES::new(&name);
+
+    let mut afd_handle: HANDLE = NULL;
+    let mut iosb = IO_STATUS_BLOCK {
+        u: IO_STATUS_BLOCK_u { Status: 0 },
+        Information: 0,
+    };
+
+    let status = unsafe {
+        NtCreateFile(
+            &mut afd_handle as PHANDLE,
+            SYNCHRONIZE,
+            &attributes as *const _ as *mut _,
+            &mut iosb as *mut _,
+            NULL as _,
+            0,
+            FILE_SHARE_READ | FILE_SHARE_WRITE,
+            FILE_OPEN,
+            0,
+            NULL,
+            0,
+        )
+    };
+
+    if status != STATUS_SUCCESS {
+        return unsafe {
+            Err(io::Error::from_raw_os_error(
+                RtlNtStatusToDosError(status) as _
+            ))
+        };
+    }
+
+    Ok(afd_handle)
+}
+
+/// Opens a helper handle on the AFD device at `path` and associates it with
+/// the completion port `iocp` under the AFD completion key.
+pub(crate) fn afd_create_helper_handle(iocp: &HANDLE, path: &str) -> io::Result<HANDLE> {
+    let afd_helper_handle = afd_open(path)?;
+
+    unsafe {
+        if (NULL == CreateIoCompletionPort(afd_helper_handle, *iocp, Key::Afd.raw(), 0))
+            || (0
+                == SetFileCompletionNotificationModes(
+                    afd_helper_handle,
+                    FILE_SKIP_SET_EVENT_ON_HANDLE,
+                ))
+        {
+            CloseHandle(afd_helper_handle);
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(afd_helper_handle)
+        }
+    }
+}
+
+/// Opens AFD devices with `NtCreateFile`, for probing.
+pub(crate) struct NtAfdDevice;
+
+impl AfdDevice for NtAfdDevice {
+    fn open(&mut self, path: &str) -> io::Result<afd::HANDLE> {
+        afd_open(path).map(|handle| handle as afd::HANDLE)
+    }
+
+    fn close(&mut self, handle: afd::HANDLE) {
+        unsafe { CloseHandle(handle as HANDLE) };
+    }
+}
+
+#[allow(non_snake_case)]
+pub(crate) fn HasOverlappedIoCompleted(Overlapped: &OVERLAPPED) -> bool {
+    //This is function is rust version impl of C++ version impl in winbase.h by Microsoft
+    Overlapped.Internal != (STATUS_PENDING as _)
+}
@@ -0,0 +1,433 @@
+use super::afd::{afd_create_helper_handle, afd_poll, HasOverlappedIoCompleted};
+use crate::core::afd::{AFD_POLL_INFO, HANDLE};
+use crate::core::driver::{Completion, Driver, PollStart};
+use crate::core::event::RawEvent;
+use crate::core::key::Key;
+use crate::core::proactor::{IoKind, IoRequest};
+use crate::core::sock::PollResult;
+use crate::core::translate::{AFD_POLL_ABORT, AFD_POLL_RECEIVE};
+use crate::core::user_event::Post;
+use miow::iocp::{CompletionPort, CompletionStatus};
+use miow::Overlapped;
+use ntapi::ntrtl::RtlNtStatusToDosError;
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::mem;
+use std::os::windows::io::AsRawHandle;
+use std::ptr::null_mut;
+use std::sync::Arc;
+use std::time::Duration;
+use winapi::shared::ntdef::{NTSTATUS, NULL};
+use winapi::shared::ntstatus::{
+    STATUS_CANCELLED, STATUS_CONNECTION_ABORTED, STATUS_CONNECTION_RESET,
+};
+use winapi::shared::winerror::{
+    ERROR_BROKEN_PIPE, ERROR_HANDLE_EOF, ERROR_INVALID_HANDLE, ERROR_INVALID_PARAMETER,
+    ERROR_IO_PENDING, ERROR_NOT_FOUND, WAIT_TIMEOUT, WSAENOTSOCK,
+};
+use winapi::shared::ws2def::WSABUF;
+use winapi::um::fileapi::{ReadFile, WriteFile};
+use winapi::um::handleapi::CloseHandle;
+use winapi::um::ioapiset::{CancelIoEx, CreateIoCompletionPort};
+use winapi::um::minwinbase::OVERLAPPED;
+use winapi::um::winnt;
+use winapi::um::winsock2::{WSAGetLastError, WSARecv, SOCKET, SOCKET_ERROR};
+
+/// The memory of an AFD poll operation. The driver writes into it until the
+/// completion has been dequeued, so it is boxed and owned by `IocpDriver`
+/// rather than by the socket.
+#[repr(C)]
+struct PollOp {
+    overlapped: OVERLAPPED,
+    poll_info: AFD_POLL_INFO,
+    id: usize,
+}
+
+impl PollOp {
+    fn result(&self) -> PollResult {
+        if self.overlapped.Internal as NTSTATUS == STATUS_CANCELLED {
+            PollResult::Cancelled
+        } else if (self.overlapped.Internal as NTSTATUS) < 0 {
+            PollResult::Failed
+        } else if self.poll_info.NumberOfHandles < 1 {
+            PollResult::Events(0)
+        } else {
+            PollResult::Events(self.poll_info.Handles[0].Events)
+        }
+    }
+}
+
+/// The memory of an overlapped read or write, owned by `IocpDriver` for the
+/// same reason as a `PollOp`.
+#[repr(C)]
+struct IoOp {
+    overlapped: OVERLAPPED,
+    request: IoRequest,
+    // The handle it runs on, for cancelling it.
+    handle: HANDLE,
+}
+
+impl IoOp {
+    fn result(&self, bytes: u32) -> io::Result<usize> {
+        let status = self.overlapped.Internal as NTSTATUS;
+        if status >= 0 {
+            return Ok(bytes as usize);
+        }
+
+        let error = unsafe { RtlNtStatusToDosError(status) };
+        match self.request.kind {
+            // Reading at the end of a file or from a pipe whose writer is
+            // gone reads nothing, like `ReadFile` reports it to std.
+            IoKind::Read if error == ERROR_HANDLE_EOF || error == ERROR_BROKEN_PIPE => Ok(0),
+            _ => Err(io::Error::from_raw_os_error(error as i32)),
+        }
+    }
+}
+
+/// The memory of a zero byte receive, owned by `IocpDriver` for the same
+/// reason as a `PollOp`.
+#[repr(C)]
+struct ZeroReadOp {
+    overlapped: OVERLAPPED,
+    id: usize,
+}
+
+impl ZeroReadOp {
+    fn result(&self) -> PollResult {
+        match self.overlapped.Internal as NTSTATUS {
+            STATUS_CANCELLED => PollResult::Cancelled,
+            STATUS_CONNECTION_RESET | STATUS_CONNECTION_ABORTED => {
+                PollResult::Events(AFD_POLL_ABORT)
+            }
+            status if status < 0 => PollResult::Failed,
+            // Data arrived, or the peer shut down its side which a read
+            // reports just the same.
+            _ => PollResult::Events(AFD_POLL_RECEIVE),
+        }
+    }
+}
+
+/// Drives AFD poll operations and the reads and writes of associated handles
+/// through an I/O completion port.
+pub(crate) struct IocpDriver {
+    port: Arc<CompletionPort>,
+    // AFD polls in flight, by the address of their OVERLAPPED.
+    polls: HashMap<usize, Box<PollOp>>,
+    // Reads and writes in flight, by the address of their OVERLAPPED.
+    ios: HashMap<usize, Box<IoOp>>,
+    // Zero byte receives in flight, by the address of their OVERLAPPED.
+    zero_reads: HashMap<usize, Box<ZeroReadOp>>,
+    // Handles this driver associated with the port.
+    associated: HashSet<HANDLE>,
+    afd_device: String,
+}
+
+impl IocpDriver {
+    /// Creates a driver opening its helper handles on the AFD device at
+    /// `afd_device`, with a completion port letting `concurrency` threads
+    /// run at once.
+    pub(crate) fn new(afd_device: &str, concurrency: u32) -> io::Result<IocpDriver> {
+        CompletionPort::new(concurrency).map(|port| IocpDriver {
+            port: Arc::new(port),
+            polls: HashMap::new(),
+            ios: HashMap::new(),
+            zero_reads: HashMap::new(),
+            associated: HashSet::new(),
+            afd_device: afd_device.to_owned(),
+        })
+    }
+}
+
+impl Driver for IocpDriver {
+    type Status = CompletionStatus;
+
+    fn open_helper(&mut self) -> io::Result<HANDLE> {
+        let iocp = self.port.as_raw_handle() as winnt::HANDLE;
+        afd_create_helper_handle(&iocp, &self.afd_device).map(|helper| helper as HANDLE)
+    }
+
+    fn close_helper(&mut self, helper: HANDLE) {
+        unsafe { CloseHandle(helper as _) };
+    }
+
+    fn start_poll(
+        &mut self,
+        helper: HANDLE,
+        base_socket: HANDLE,
+        afd_events: u32,
+        id: usize,
+    ) -> io::Result<PollStart> {
+        let mut op = Box::new(PollOp {
+            overlapped: OVERLAPPED::default(),
+            poll_info: AFD_POLL_INFO::new(),
+            id,
+        });
+        op.poll_info.Timeout.QuadPart = i64::MAX;
+        op.poll_info.Handles[0].Handle = base_socket;
+        op.poll_info.Handles[0].Events = afd_events;
+
+        let op_ref = &mut *op;
+        match afd_poll(helper as _, &mut op_ref.poll_info, &mut op_ref.overlapped) {
+            // A poll which completed right away still queues its completion.
+            Ok(()) => {}
+            Err(ref e) if e.raw_os_error() == Some(ERROR_IO_PENDING as _) => {}
+            Err(ref e) if e.raw_os_error() == Some(ERROR_INVALID_HANDLE as _) => {
+                return Ok(PollStart::SocketClosed);
+            }
+            Err(e) => return Err(e),
+        }
+
+        let address = &op.overlapped as *const _ as usize;
+        self.polls.insert(address, op);
+        Ok(PollStart::Pending)
+    }
+
+    fn cancel_poll(&mut self, helper: HANDLE, id: usize) -> io::Result<()> {
+        let op = match self.polls.values_mut().find(|op| op.id == id) {
+            Some(op) => op,
+            None => return Ok(()),
+        };
+        if HasOverlappedIoCompleted(&op.overlapped) {
+            return Ok(());
+        }
+
+        match unsafe { CancelIoEx(helper as _, &mut op.overlapped) } {
+            0 => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    fn prepare_zero_read(&mut self, socket: HANDLE) -> io::Result<()> {
+        // A socket is associated with a port once and for all, which makes
+        // further attempts fail with `ERROR_INVALID_PARAMETER`. Unless it was
+        // this driver, the completions go to another port.
+        let iocp = self.port.as_raw_handle() as winnt::HANDLE;
+        if unsafe { CreateIoCompletionPort(socket as _, iocp, Key::ZeroRead.raw(), 0) } == NULL {
+            let err = io::Error::last_os_error();
+            return match err.raw_os_error().map(|code| code as u32) {
+                Some(ERROR_INVALID_PARAMETER) if self.associated.contains(&socket) => Ok(()),
+                Some(ERROR_INVALID_PARAMETER) => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "socket is associated with another completion port",
+                )),
+                _ => Err(err),
+            };
+        }
+
+        self.associated.insert(socket);
+        Ok(())
+    }
+
+    fn start_zero_read(&mut self, socket: HANDLE, id: usize) -> io::Result<PollStart> {
+        let mut op = Box::new(ZeroReadOp {
+            overlapped: OVERLAPPED::default(),
+            id,
+        });
+        let mut buf = WSABUF {
+            len: 0,
+            buf: null_mut(),
+        };
+        let mut flags = 0;
+        let result = unsafe {
+            WSARecv(
+                socket as SOCKET,
+                &mut buf,
+                1,
+                null_mut(),
+                &mut flags,
+                &mut op.overlapped,
+                None,
+            )
+        };
+        // A receive which completed right away still queues its completion.
+        if result == SOCKET_ERROR {
+            match unsafe { WSAGetLastError() } {
+                code if code == ERROR_IO_PENDING as i32 => {}
+                code if code == WSAENOTSOCK as i32 => return Ok(PollStart::SocketClosed),
+                code => return Err(io::Error::from_raw_os_error(code)),
+            }
+        }
+
+        let address = &op.overlapped as *const _ as usize;
+        self.zero_reads.insert(address, op);
+        Ok(PollStart::Pending)
+    }
+
+    fn cancel_zero_read(&mut self, socket: HANDLE, id: usize) -> io::Result<()> {
+        let op = match self.zero_reads.values_mut().find(|op| op.id == id) {
+            Some(op) => op,
+            None => return Ok(()),
+        };
+        if HasOverlappedIoCompleted(&op.overlapped) {
+            return Ok(());
+        }
+
+        match unsafe { CancelIoEx(socket as _, &mut op.overlapped) } {
+            0 => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    fn wait(
+        &mut self,
+        statuses: &mut [CompletionStatus],
+        timeout: Option<Duration>,
+    ) -> io::Result<usize> {
+        match self.port.get_many(statuses, timeout) {
+            Ok(statuses) => Ok(statuses.len()),
+            Err(ref e) if e.raw_os_error() == Some(WAIT_TIMEOUT as i32) => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn completion(&mut self, status: &CompletionStatus) -> Option<Completion> {
+        // Looked up rather than dereferenced whatever the key, the handle may
+        // have been associated with another selector's port as well.
+        let address = status.overlapped() as usize;
+        match Key::from_raw(status.token()) {
+            Key::Afd => {
+                let op = self.polls.remove(&address)?;
+                Some(Completion::Poll {
+                    id: op.id,
+                    result: op.result(),
+                })
+            }
+            Key::Waker => Some(Completion::Wake),
+            // A socket associated for reads and writes before it was probed
+            // with zero byte reads completes both with the first key.
+            Key::Io | Key::ZeroRead => {
+                if let Some(op) = self.ios.remove(&address) {
+                    let result = op.result(status.bytes_transferred());
+                    return Some(Completion::Io(op.request.complete(result)));
+                }
+                let op = self.zero_reads.remove(&address)?;
+                Some(Completion::Poll {
+                    id: op.id,
+                    result: op.result(),
+                })
+            }
+            Key::User => Some(Completion::User { id: address }),
+            // Foreign packets point at memory of their own.
+            Key::Foreign(_) => None,
+        }
+    }
+
+    fn poster(&self) -> Arc<dyn Post> {
+        self.port.clone()
+    }
+
+    fn associate(&mut self, handle: HANDLE) -> io::Result<()> {
+        let iocp = self.port.as_raw_handle() as winnt::HANDLE;
+        match unsafe { CreateIoCompletionPort(handle as _, iocp, Key::Io.raw(), 0) } {
+            NULL => Err(io::Error::last_os_error()),
+            _ => {
+                self.associated.insert(handle);
+                Ok(())
+            }
+        }
+    }
+
+    fn submit(&mut self, handle: HANDLE, request: IoRequest) -> io::Result<()> {
+        let mut op = Box::new(IoOp {
+            overlapped: OVERLAPPED::default(),
+            request,
+            handle,
+        });
+        unsafe {
+            let position = op.overlapped.u.s_mut();
+            position.Offset = op.request.offset as u32;
+            position.OffsetHigh = (op.request.offset >> 32) as u32;
+        }
+
+        let op_ref = &mut *op;
+        let buf = op_ref.request.buf.as_mut_ptr();
+        let len = cmp::min(op_ref.request.buf.len(), u32::MAX as usize) as u32;
+        let ok = match op_ref.request.kind {
+            IoKind::Read => unsafe {
+                ReadFile(
+                    handle as _,
+                    buf as _,
+                    len,
+                    null_mut(),
+                    &mut op_ref.overlapped,
+                )
+            },
+            IoKind::Write => unsafe {
+                WriteFile(
+                    handle as _,
+                    buf as _,
+                    len,
+                    null_mut(),
+                    &mut op_ref.overlapped,
+                )
+            },
+        };
+        // Operations which complete right away still queue their completion.
+        if ok == 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() != Some(ERROR_IO_PENDING as _) {
+                return Err(err);
+            }
+        }
+
+        let address = &op.overlapped as *const _ as usize;
+        self.ios.insert(address, op);
+        Ok(())
+    }
+
+    fn cancel_ios(&mut self) -> io::Result<()> {
+        for op in self.ios.values_mut() {
+            if HasOverlappedIoCompleted(&op.overlapped) {
+                continue;
+            }
+
+            if unsafe { CancelIoEx(op.handle as _, &mut op.overlapped) } == 0 {
+                // Completed in the meantime, or cancelled already by closing
+                // the handle, the completion is queued all the same.
+                let err = io::Error::last_os_error();
+                match err.raw_os_error().map(|code| code as u32) {
+                    Some(ERROR_NOT_FOUND) | Some(ERROR_INVALID_HANDLE) => {}
+                    _ => return Err(err),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn ios_in_flight(&self) -> bool {
+        !self.ios.is_empty()
+    }
+}
+
+impl Post for CompletionPort {
+    fn post(&self, id: usize) -> io::Result<()> {
+        // The packet is never dereferenced, see `completion`.
+        let status = CompletionStatus::new(0, Key::User.raw(), id as *mut Overlapped);
+        CompletionPort::post(self, status)
+    }
+}
+
+impl Drop for IocpDriver {
+    fn drop(&mut self) {
+        // The port cancels every operation before it lets the driver go.
+        // Should one still be in flight because cancelling failed, the kernel
+        // may write into its memory after this point, so it is leaked rather
+        // than freed.
+        for (_, op) in self.polls.drain() {
+            mem::forget(op);
+        }
+        for (_, op) in self.zero_reads.drain() {
+            mem::forget(op);
+        }
+        for (_, op) in self.ios.drain() {
+            mem::forget(op);
+        }
+    }
+}
+
+impl RawEvent for CompletionStatus {
+    fn zeroed() -> CompletionStatus {
+        CompletionStatus::zero()
+    }
+}
@@ -0,0 +1,236 @@
+mod afd;
+mod iocp;
+mod poll;
+mod selector;
+mod tcp;
+mod ws;
+mod wsapoll;
+
+pub use self::poll::{poll_once, SocketRef};
+pub use self::selector::{Events, Selector};
+pub use self::tcp::TcpStream;
+
+#[cfg(test)]
+mod tests {
+    use super::afd::{afd_create_helper_handle, afd_poll};
+    use super::ws::{ws_get_base_socket, ws_global_init};
+    use crate::core::afd::AFD_POLL_INFO;
+    use crate::core::backend::DEFAULT_AFD_DEVICE;
+    use crate::core::buffer_pool::{BufferPool, BufferPoolBuilder, PooledBuf};
+    use crate::core::key::Key;
+    use crate::core::translate::{
+        sock_afd_events_to_epoll_events, sock_epoll_events_to_afd_events, EPOLLERR, EPOLLHUP,
+        EPOLLIN, EPOLLOUT,
+    };
+    use std::cmp;
+    use std::io::{self, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::os::windows::io::AsRawSocket;
+    use std::ptr::null_mut;
+    use std::sync::mpsc;
+    use std::{thread, time};
+    use winapi::shared::minwindef::{DWORD, FALSE, ULONG};
+    use winapi::shared::ntdef::NULL;
+    use winapi::shared::ws2def::WSABUF;
+    use winapi::um::handleapi::INVALID_HANDLE_VALUE;
+    use winapi::um::ioapiset::{CancelIoEx, CreateIoCompletionPort, GetQueuedCompletionStatusEx};
+    use winapi::um::minwinbase::{OVERLAPPED, OVERLAPPED_ENTRY};
+    use winapi::um::winnt::HANDLE;
+    use winapi::um::winsock2::{WSAGetLastError, WSARecv, SOCKET, SOCKET_ERROR, WSA_IO_PENDING};
+
+    #[repr(C)]
+    struct PollInfoBinding {
+        overlapped: OVERLAPPED,
+        poll_info: AFD_POLL_INFO,
+    }
+
+    // A receive in flight, which owns the buffer the kernel writes into.
+    #[repr(C)]
+    struct RecvOp {
+        overlapped: OVERLAPPED,
+        buf: PooledBuf,
+    }
+
+    fn start_recv(sock: SOCKET, pool: &BufferPool) -> io::Result<Box<RecvOp>> {
+        let mut op = Box::new(RecvOp {
+            overlapped: OVERLAPPED::default(),
+            buf: pool.acquire()?,
+        });
+        let mut buf = unsafe { slice2buf(&op.buf) };
+        let mut flags = 0;
+        let r = unsafe {
+            WSARecv(
+                sock,
+                &mut buf,
+                1,
+                null_mut(),
+                &mut flags,
+                &mut op.overlapped,
+                None,
+            )
+        };
+        match r {
+            SOCKET_ERROR => match unsafe { WSAGetLastError() } {
+                WSA_IO_PENDING => Ok(op),
+                err => Err(io::Error::from_raw_os_error(err)),
+            },
+            _ => Ok(op),
+        }
+    }
+
+    // Waits for the completion of `op`, which has the socket to itself.
+    fn complete_recv(iocp: HANDLE, op: &RecvOp) -> io::Result<OVERLAPPED_ENTRY> {
+        let mut entry = OVERLAPPED_ENTRY::default();
+        let mut count: ULONG = 0;
+        if unsafe { GetQueuedCompletionStatusEx(iocp, &mut entry, 1, &mut count, 3000, FALSE) }
+            == FALSE
+        {
+            return Err(io::Error::last_os_error());
+        }
+        assert_eq!(entry.lpCompletionKey, Key::Io.raw());
+        assert_eq!(entry.lpOverlapped as *const _, &op.overlapped as *const _);
+        Ok(entry)
+    }
+
+    unsafe fn slice2buf(slice: &[u8]) -> WSABUF {
+        WSABUF {
+            len: cmp::min(slice.len(), u32::MAX as usize) as u32,
+            buf: slice.as_ptr() as *mut _,
+        }
+    }
+
+    #[allow(non_snake_case)]
+    fn port__create_iocp() -> io::Result<HANDLE> {
+        //just return the result, error handling left for future
+        let iocp = unsafe { CreateIoCompletionPort(INVALID_HANDLE_VALUE, NULL, 0, 0) };
+
+        match iocp {
+            NULL => Err(io::Error::last_os_error()),
+            _ => Ok(iocp),
+        }
+    }
+
+    #[test]
+    fn test_tcp_listener() -> io::Result<()> {
+        //epoll_create() start
+        ws_global_init()?;
+
+        let iocp: HANDLE = port__create_iocp().unwrap();
+        //epoll_create() end
+
+        //create test socket
+        //Spawn thread to connect to TcpListener, it sends a byte when told to
+        let (send, sent) = mpsc::channel::<()>();
+        let peer = thread::spawn(move || {
+            let one_sec = time::Duration::from_secs(1);
+            thread::sleep(one_sec);
+            let mut stream = TcpStream::connect("127.0.0.1:12345").unwrap();
+            while sent.recv().is_ok() {
+                stream.write_all(b"x").unwrap();
+            }
+        });
+
+        //Create listener
+        let listener = TcpListener::bind("127.0.0.1:12345").unwrap();
+        let (net_sock, _) = listener.accept().unwrap();
+        let sock = net_sock.as_raw_socket() as SOCKET;
+        std::mem::forget(listener);
+        std::mem::forget(net_sock);
+        let socket_event: u32 = EPOLLERR | EPOLLHUP | EPOLLIN | EPOLLOUT;
+
+        // A receive is pending while the socket is polled. Its buffer is lent
+        // by the pool until the operation and its buffer are dropped.
+        let pool = BufferPoolBuilder::new().buffer_size(256).build()?;
+        if unsafe { CreateIoCompletionPort(sock as HANDLE, iocp, Key::Io.raw(), 0) }.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        let op = start_recv(sock, &pool)?;
+        assert_eq!(pool.stats().outstanding, 1);
+
+        //port__ctl_add() start
+        let base_sock = ws_get_base_socket(&sock).unwrap();
+
+        let afd_helper_handle = afd_create_helper_handle(&iocp, DEFAULT_AFD_DEVICE).unwrap();
+        println!("{:?}", afd_helper_handle);
+
+        let mut binding = Box::new(PollInfoBinding {
+            overlapped: OVERLAPPED::default(),
+            poll_info: AFD_POLL_INFO::new(),
+        });
+        binding.poll_info.Timeout.QuadPart = i64::MAX;
+        binding.poll_info.Handles[0].Handle = base_sock;
+        binding.poll_info.Handles[0].Events = sock_epoll_events_to_afd_events(socket_event);
+        //memset(&sock_state->overlapped, 0, sizeof sock_state->overlapped);
+
+        afd_poll(
+            afd_helper_handle,
+            &mut binding.poll_info,
+            &mut binding.overlapped,
+        )
+        .unwrap();
+        //port__ctl_add() end
+
+        //epoll_wait start
+        let mut completion_count: DWORD = 0;
+        let mut iocp_events: [OVERLAPPED_ENTRY; 256] = [OVERLAPPED_ENTRY::default(); 256];
+        let _r = unsafe {
+            GetQueuedCompletionStatusEx(
+                iocp,
+                iocp_events.as_mut_ptr(),
+                iocp_events.len() as ULONG,
+                &mut completion_count as *mut _,
+                //INFINITE,
+                //Just wait 3 second for testing
+                3000,
+                FALSE,
+            )
+        };
+        //epoll_wait end
+
+        //println!("Return value: {:?}", r);
+        //println!("completion_count: {:?}", completion_count);
+        //println!("iocp_events: ");
+        assert_eq!(completion_count, 1);
+        for ele in iocp_events[0..completion_count as usize].iter() {
+            //println!("  Event: ");
+            //println!("    lpCompletionKey: {:?}", ele.lpCompletionKey);
+            //println!("    lpOverlapped: {:?}", ele.lpOverlapped);
+            if NULL as *const OVERLAPPED != ele.lpOverlapped {
+                unsafe {
+                    let afd_poll_info = &(*(ele.lpOverlapped as *const PollInfoBinding)).poll_info;
+                    let iocp_events =
+                        sock_afd_events_to_epoll_events(afd_poll_info.Handles[0].Events);
+                    //println!("      events: 0x{:x?}", iocp_events);
+                    assert!(iocp_events & EPOLLOUT != 0);
+                }
+            }
+            //println!("    Internal: {:?}", ele.Internal);
+            //println!(
+            //"    dwNumberOfBytesTransferred: {:?}",
+            //ele.dwNumberOfBytesTransferred
+            //);
+        }
+
+        // The buffer returns once the receive completed and is dropped.
+        send.send(()).unwrap();
+        let entry = complete_recv(iocp, &op)?;
+        assert_eq!(entry.dwNumberOfBytesTransferred, 1);
+        assert_eq!(op.buf[0], b'x');
+        drop(op);
+        assert_eq!(pool.stats().outstanding, 0);
+
+        // And once it was cancelled.
+        let op = start_recv(sock, &pool)?;
+        assert_eq!(pool.stats().outstanding, 1);
+        if unsafe { CancelIoEx(sock as HANDLE, &op.overlapped as *const _ as *mut _) } == FALSE {
+            return Err(io::Error::last_os_error());
+        }
+        complete_recv(iocp, &op)?;
+        drop(op);
+        assert_eq!(pool.stats().outstanding, 0);
+
+        drop(send);
+        peer.join().unwrap();
+        Ok(())
+    }
+}
@@ -0,0 +1,218 @@
+use super::afd::{afd_open, afd_poll_many};
+use super::ws::{winsock, ws_get_base_socket};
+use crate::core::afd::{AfdPollInfoBuf, AFD_POLL_HANDLE_INFO, HANDLE};
+use crate::core::backend::DEFAULT_AFD_DEVICE;
+use crate::core::interests::Interests;
+use crate::core::poll_once::{afd_events, afd_readiness, afd_timeout};
+use crate::core::ready::Ready;
+use crate::core::translate::AFD_POLL_LOCAL_CLOSE;
+use ntapi::ntrtl::RtlNtStatusToDosError;
+use std::io;
+use std::marker::PhantomData;
+use std::os::windows::io::{AsRawSocket, RawSocket};
+use std::ptr::null_mut;
+use std::thread;
+use std::time::Duration;
+use winapi::shared::minwindef::{FALSE, TRUE};
+use winapi::shared::ntdef::{NTSTATUS, NULL};
+use winapi::shared::winerror::ERROR_IO_PENDING;
+use winapi::um::handleapi::CloseHandle;
+use winapi::um::ioapiset::CancelIoEx;
+use winapi::um::minwinbase::OVERLAPPED;
+use winapi::um::synchapi::{CreateEventW, WaitForSingleObject};
+use winapi::um::winbase::{INFINITE, WAIT_OBJECT_0};
+use winapi::um::winnt;
+use winapi::um::winsock2::SOCKET;
+
+/// A socket borrowed for `poll_once`.
+#[derive(Debug, Clone, Copy)]
+pub struct SocketRef<'a> {
+    socket: RawSocket,
+    _socket: PhantomData<&'a ()>,
+}
+
+impl<'a> SocketRef<'a> {
+    pub fn new<S: AsRawSocket>(socket: &'a S) -> SocketRef<'a> {
+        SocketRef {
+            socket: socket.as_raw_socket(),
+            _socket: PhantomData,
+        }
+    }
+}
+
+impl<'a, S: AsRawSocket> From<&'a S> for SocketRef<'a> {
+    fn from(socket: &'a S) -> SocketRef<'a> {
+        SocketRef::new(socket)
+    }
+}
+
+/// Closes a handle when dropped.
+struct OwnedHandle(winnt::HANDLE);
+
+impl Drop for OwnedHandle {
+    fn drop(&mut self) {
+        unsafe { CloseHandle(self.0) };
+    }
+}
+
+/// Waits until one of `sockets` is ready for its interests or `timeout` has
+/// elapsed, and stores the readiness of every socket next to it. Returns how
+/// many are ready, none if the timeout elapsed.
+///
+/// Errors and hang-ups are reported whatever the interests, a connect which
+/// failed as an error together with writability, which `WSAPoll` does not
+/// report at all. A handle which is no socket is reported as an error.
+///
+/// All sockets are polled with a single AFD poll on a helper handle opened
+/// for the call, so the AFD device is required. Without sockets the call
+/// only waits for the timeout, like `poll(2)` does.
+pub fn poll_once(
+    sockets: &mut [(SocketRef<'_>, Interests, Ready)],
+    timeout: Option<Duration>,
+) -> io::Result<usize> {
+    if sockets.is_empty() {
+        match timeout {
+            Some(timeout) => thread::sleep(timeout),
+            None => loop {
+                thread::park();
+            },
+        }
+        return Ok(0);
+    }
+
+    let _winsock = winsock()?;
+    let mut poll_info = AfdPollInfoBuf::new(sockets.len());
+    // The AFD events of every socket, by index.
+    let mut reported = vec![0; sockets.len()];
+    let mut base_sockets = vec![None; sockets.len()];
+
+    let mut polled = 0;
+    for (i, &(socket, interests, _)) in sockets.iter().enumerate() {
+        match ws_get_base_socket(&(socket.socket as SOCKET)) {
+            Ok(base_socket) => {
+                let base_socket = base_socket as HANDLE;
+                poll_info.handles_mut()[polled] = AFD_POLL_HANDLE_INFO {
+                    Handle: base_socket,
+                    Events: afd_events(interests),
+                    Status: 0,
+                };
+                base_sockets[i] = Some(base_socket);
+                polled += 1;
+            }
+            // Closed, or never a socket.
+            Err(ref e) if e.kind() == io::ErrorKind::InvalidInput => {
+                reported[i] = AFD_POLL_LOCAL_CLOSE;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    if polled > 0 {
+        // A socket reported already leaves nothing to wait for.
+        let timeout = match reported.iter().any(|&events| events != 0) {
+            true => Some(Duration::from_secs(0)),
+            false => timeout,
+        };
+        let info = poll_info.info_mut();
+        info.NumberOfHandles = polled as u32;
+        info.Exclusive = FALSE as u32;
+        info.Timeout.QuadPart = afd_timeout(timeout);
+        afd_poll_blocking(&mut poll_info)?;
+
+        for handle in poll_info.reported() {
+            let matching = base_sockets
+                .iter()
+                .zip(reported.iter_mut())
+                .filter(|&(&base_socket, _)| base_socket == Some(handle.Handle));
+            for (_, events) in matching {
+                *events |= handle.Events;
+            }
+        }
+    }
+
+    let mut ready = 0;
+    for (&mut (_, interests, ref mut readiness), &events) in sockets.iter_mut().zip(&reported) {
+        *readiness = afd_readiness(events, interests);
+        if !readiness.is_empty() {
+            ready += 1;
+        }
+    }
+    Ok(ready)
+}
+
+/// Runs an AFD poll to completion on a helper handle of its own, which is
+/// not associated with any completion port. Completion is awaited on an
+/// event, the driver applies the timeout of `poll_info`.
+fn afd_poll_blocking(poll_info: &mut AfdPollInfoBuf) -> io::Result<()> {
+    let helper = OwnedHandle(afd_open(DEFAULT_AFD_DEVICE)?);
+    let event = match unsafe { CreateEventW(null_mut(), TRUE, FALSE, null_mut()) } {
+        NULL => return Err(io::Error::last_os_error()),
+        event => OwnedHandle(event),
+    };
+    let mut overlapped = OVERLAPPED {
+        hEvent: event.0,
+        ..Default::default()
+    };
+
+    match afd_poll_many(helper.0, poll_info, &mut overlapped) {
+        Ok(()) => {}
+        Err(ref e) if e.raw_os_error() == Some(ERROR_IO_PENDING as _) => {
+            if unsafe { WaitForSingleObject(event.0, INFINITE) } != WAIT_OBJECT_0 {
+                let err = io::Error::last_os_error();
+                // The driver writes into `poll_info` until the poll is over.
+                unsafe {
+                    CancelIoEx(helper.0, &mut overlapped);
+                    WaitForSingleObject(event.0, INFINITE);
+                }
+                return Err(err);
+            }
+        }
+        Err(e) => return Err(e),
+    }
+
+    match overlapped.Internal as NTSTATUS {
+        status if status < 0 => {
+            Err(io::Error::from_raw_os_error(
+                unsafe { RtlNtStatusToDosError(status) } as i32,
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::{self, TcpListener};
+    use std::time::Instant;
+
+    #[test]
+    fn sockets_report_what_they_are_ready_for() -> io::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let peer = net::TcpStream::connect(listener.local_addr()?)?;
+        let (accepted, _) = listener.accept()?;
+
+        let mut sockets = [
+            (SocketRef::new(&accepted), Interests::READABLE, Ready::EMPTY),
+            (SocketRef::new(&peer), Interests::READABLE, Ready::EMPTY),
+        ];
+        let timeout = Some(Duration::from_millis(10));
+        assert_eq!(poll_once(&mut sockets, timeout)?, 0);
+
+        (&peer).write_all(b"x")?;
+        sockets[1].1 = Interests::WRITABLE;
+        assert_eq!(poll_once(&mut sockets, None)?, 2);
+        assert_eq!(sockets[0].2, Ready::READABLE);
+        assert_eq!(sockets[1].2, Ready::WRITABLE);
+        Ok(())
+    }
+
+    #[test]
+    fn no_sockets_wait_for_the_timeout() -> io::Result<()> {
+        let start = Instant::now();
+        assert_eq!(poll_once(&mut [], Some(Duration::from_millis(20)))?, 0);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        Ok(())
+    }
+}
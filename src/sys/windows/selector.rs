@@ -0,0 +1,266 @@
+use super::afd::NtAfdDevice;
+use super::iocp::IocpDriver;
+use super::tcp::TcpStream;
+use super::ws::{winsock, ws_get_base_socket, Winsock};
+use super::wsapoll::WsaPollDriver;
+use crate::core::afd::HANDLE;
+use crate::core::backend::{choose_backend, probe_afd, Backend};
+use crate::core::builder::SelectorBuilder;
+use crate::core::event;
+use crate::core::interests::Interests;
+use crate::core::library::LibraryRef;
+use crate::core::port::{not_registered, Port};
+use crate::core::proactor::{IoBuf, IoHandle, IoKind};
+use crate::core::strategy::Strategy;
+use crate::core::token::Token;
+use crate::core::user_event::UserEvent;
+use miow::iocp::CompletionStatus;
+use std::io;
+use std::os::windows::io::AsRawHandle;
+use std::time::{Duration, Instant};
+
+/// Dropping a `Selector` cancels every pending AFD poll, waits for the
+/// cancellations to complete and closes the helper handles and the
+/// completion port.
+pub struct Selector {
+    inner: Inner,
+    // Dropped last, Winsock is cleaned up once the port is gone.
+    _winsock: LibraryRef<'static, Winsock>,
+}
+
+enum Inner {
+    Afd(Port<IocpDriver>),
+    WsaPoll(Port<WsaPollDriver>),
+}
+
+impl Selector {
+    pub fn new() -> io::Result<Selector> {
+        SelectorBuilder::default().build()
+    }
+
+    /// Returns the mechanism the selector waits with.
+    pub fn backend(&self) -> Backend {
+        match self.inner {
+            Inner::Afd(_) => Backend::Afd,
+            Inner::WsaPoll(_) => Backend::WsaPoll,
+        }
+    }
+
+    pub fn select(&mut self, events: &mut Events, timeout: Option<Duration>) -> io::Result<()> {
+        match self.inner {
+            Inner::Afd(ref mut port) => port.select(events, timeout),
+            Inner::WsaPoll(ref mut port) => port.select(events, timeout),
+        }
+    }
+
+    /// Waits for events until `deadline`, which is kept to below a
+    /// millisecond even though the completion port counts in milliseconds.
+    /// A deadline which has passed only takes the events already there.
+    pub fn select_until(&mut self, events: &mut Events, deadline: Instant) -> io::Result<()> {
+        match self.inner {
+            Inner::Afd(ref mut port) => port.select_until(events, deadline),
+            Inner::WsaPoll(ref mut port) => port.select_until(events, deadline),
+        }
+    }
+
+    /// Registers `sock` with `Strategy::Auto`.
+    pub fn register(
+        &mut self,
+        sock: &mut TcpStream,
+        token: Token,
+        interests: Interests,
+    ) -> io::Result<()> {
+        self.register_with(sock, token, interests, Strategy::Auto)
+    }
+
+    /// Registers `sock`, observing its readiness as `strategy` says.
+    pub fn register_with(
+        &mut self,
+        sock: &mut TcpStream,
+        token: Token,
+        interests: Interests,
+        strategy: Strategy,
+    ) -> io::Result<()> {
+        let socket = sock.socket();
+        let base_socket = ws_get_base_socket(&socket).map(|base_socket| base_socket as HANDLE);
+        let socket = socket as HANDLE;
+        let registration = match self.inner {
+            Inner::Afd(ref mut port) => {
+                port.register_with(socket, base_socket, token, interests, strategy)?
+            }
+            Inner::WsaPoll(ref mut port) => {
+                port.register_with(socket, base_socket, token, interests, strategy)?
+            }
+        };
+        sock.set_registration(registration);
+        Ok(())
+    }
+
+    /// Changes the token and interests of a registered stream. A stream
+    /// probed with zero byte reads can only be interested in reading.
+    pub fn reregister(
+        &mut self,
+        sock: &mut TcpStream,
+        token: Token,
+        interests: Interests,
+    ) -> io::Result<()> {
+        let registration = sock.registration().ok_or_else(not_registered)?;
+        match self.inner {
+            Inner::Afd(ref mut port) => port.reregister(registration, token, interests),
+            Inner::WsaPoll(ref mut port) => port.reregister(registration, token, interests),
+        }
+    }
+
+    /// Deregisters a stream right away, rather than by the next `select` as
+    /// dropping it does.
+    pub fn deregister(&mut self, sock: &mut TcpStream) -> io::Result<()> {
+        let registration = sock.registration_mut().ok_or_else(not_registered)?;
+        match self.inner {
+            Inner::Afd(ref mut port) => port.deregister(registration)?,
+            Inner::WsaPoll(ref mut port) => port.deregister(registration)?,
+        }
+        sock.clear_registration();
+        Ok(())
+    }
+
+    /// Associates `handle`, which has to be opened for overlapped I/O, with
+    /// the completion port for completion based I/O. Its completions are
+    /// reported with `token`. Only available with the AFD backend.
+    pub fn associate<H: AsRawHandle>(&mut self, handle: &H, token: Token) -> io::Result<IoHandle> {
+        let handle = handle.as_raw_handle() as HANDLE;
+        match self.inner {
+            Inner::Afd(ref mut port) => port.associate(handle, token),
+            Inner::WsaPoll(ref mut port) => port.associate(handle, token),
+        }
+    }
+
+    /// Starts reading into `buf` at `offset` of an associated handle. If it
+    /// cannot be started the buffer is dropped.
+    pub fn submit_read<B: Into<IoBuf>>(
+        &mut self,
+        handle: &IoHandle,
+        buf: B,
+        offset: u64,
+    ) -> io::Result<()> {
+        self.submit(handle, IoKind::Read, buf.into(), offset)
+    }
+
+    /// Starts writing `buf` at `offset` of an associated handle. If it
+    /// cannot be started the buffer is dropped.
+    pub fn submit_write<B: Into<IoBuf>>(
+        &mut self,
+        handle: &IoHandle,
+        buf: B,
+        offset: u64,
+    ) -> io::Result<()> {
+        self.submit(handle, IoKind::Write, buf.into(), offset)
+    }
+
+    fn submit(
+        &mut self,
+        handle: &IoHandle,
+        kind: IoKind,
+        buf: IoBuf,
+        offset: u64,
+    ) -> io::Result<()> {
+        match self.inner {
+            Inner::Afd(ref mut port) => port.submit(handle, kind, buf, offset),
+            Inner::WsaPoll(ref mut port) => port.submit(handle, kind, buf, offset),
+        }
+    }
+
+    /// Creates a user event whose triggers `select` reports with `token`.
+    pub fn user_event(&mut self, token: Token) -> io::Result<UserEvent> {
+        match self.inner {
+            Inner::Afd(ref mut port) => port.user_event(token),
+            Inner::WsaPoll(ref mut port) => port.user_event(token),
+        }
+    }
+}
+
+impl SelectorBuilder {
+    /// Creates a selector with this configuration. Unless a backend is
+    /// required, AFD polling is used if the AFD device opens and `WSAPoll`
+    /// otherwise.
+    pub fn build(&self) -> io::Result<Selector> {
+        let winsock = winsock()?;
+
+        let backend = match self.required_backend(&[Backend::Afd, Backend::WsaPoll])? {
+            Some(Backend::Afd) => {
+                self.probe()?;
+                Backend::Afd
+            }
+            Some(backend) => backend,
+            None => choose_backend(&mut NtAfdDevice, &self.afd_device),
+        };
+
+        let inner = match backend {
+            Backend::Afd => {
+                let driver = IocpDriver::new(&self.afd_device, self.concurrency)?;
+                Inner::Afd(Port::new(driver, self, backend)?)
+            }
+            _ => Inner::WsaPoll(Port::new(WsaPollDriver::new(), self, backend)?),
+        };
+        Ok(Selector {
+            inner,
+            _winsock: winsock,
+        })
+    }
+
+    /// Reports whether AFD polling is usable with this configuration, the
+    /// error tells why it is not.
+    pub fn probe(&self) -> io::Result<()> {
+        let _winsock = winsock()?;
+
+        probe_afd(&mut NtAfdDevice, &self.afd_device).map_err(io::Error::from)
+    }
+}
+
+pub type Events = event::Events<CompletionStatus>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{self, TcpListener};
+
+    #[test]
+    fn streams_are_reregistered_and_deregistered() -> io::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let _peer = net::TcpStream::connect(listener.local_addr()?)?;
+        let mut stream = TcpStream::from_std(listener.accept()?.0);
+
+        let mut selector = Selector::new()?;
+        let mut events = Events::with_capacity(8);
+        let not_found =
+            |result: io::Result<()>| result.unwrap_err().kind() == io::ErrorKind::NotFound;
+        assert!(not_found(selector.reregister(
+            &mut stream,
+            Token(1),
+            Interests::WRITABLE
+        )));
+
+        selector.register(&mut stream, Token(1), Interests::READABLE)?;
+        selector.select(&mut events, Some(Duration::from_millis(10)))?;
+        assert!(events.is_empty());
+        selector.reregister(&mut stream, Token(2), Interests::WRITABLE)?;
+        selector.select(&mut events, Some(Duration::from_secs(1)))?;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events.get(0).unwrap().token(), Token(2));
+
+        // The registration stays with the stream if it is not ours.
+        assert!(not_found(Selector::new()?.deregister(&mut stream)));
+        selector.deregister(&mut stream)?;
+        assert!(not_found(selector.deregister(&mut stream)));
+        assert!(not_found(selector.reregister(
+            &mut stream,
+            Token(2),
+            Interests::WRITABLE
+        )));
+
+        selector.register(&mut stream, Token(3), Interests::WRITABLE)?;
+        selector.select(&mut events, Some(Duration::from_secs(1)))?;
+        let tokens: Vec<Token> = events.iter().map(|event| event.token()).collect();
+        assert_eq!(tokens, [Token(3)]);
+        Ok(())
+    }
+}
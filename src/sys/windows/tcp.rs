@@ -0,0 +1,103 @@
+use super::poll::{poll_once, SocketRef};
+use crate::core::interests::Interests;
+use crate::core::port::Registration;
+use crate::core::ready::Ready;
+use std::cmp;
+use std::io::{self, Read, Write};
+use std::net;
+use std::os::windows::io::{AsRawSocket, RawSocket};
+use std::time::Duration;
+use winapi::ctypes::{c_char, c_int};
+use winapi::um::winsock2::{recv, send, WSAGetLastError, MSG_OOB, SOCKET, SOCKET_ERROR};
+
+/// Dropping a registered stream closes the socket right away, its selector
+/// only deregisters it by the next `select` and reports nothing for it in
+/// the meantime. `Selector::deregister` deregisters it before it is closed.
+pub struct TcpStream {
+    registration: Option<Registration>,
+    sock: net::TcpStream,
+}
+
+impl TcpStream {
+    pub fn from_std(socket: net::TcpStream) -> TcpStream {
+        TcpStream {
+            registration: None,
+            sock: socket,
+        }
+    }
+
+    /// Waits until the stream is ready for `interests`, without a selector,
+    /// and returns its readiness, which is empty if `timeout` elapsed first.
+    /// Errors and hang-ups are reported whatever the interests.
+    pub fn wait(&self, interests: Interests, timeout: Option<Duration>) -> io::Result<Ready> {
+        let mut socket = [(SocketRef::new(self), interests, Ready::EMPTY)];
+        poll_once(&mut socket, timeout)?;
+        Ok(socket[0].2)
+    }
+
+    /// Receives out-of-band data, which is announced by priority readiness.
+    pub fn recv_oob(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = cmp::min(buf.len(), c_int::MAX as usize) as c_int;
+        let n = unsafe { recv(self.socket(), buf.as_mut_ptr() as *mut c_char, len, MSG_OOB) };
+        if n == SOCKET_ERROR {
+            Err(io::Error::from_raw_os_error(unsafe { WSAGetLastError() }))
+        } else {
+            Ok(n as usize)
+        }
+    }
+
+    /// Sends `buf` as out-of-band data. TCP only marks the last byte as
+    /// urgent, so peers usually send a single byte at a time.
+    pub fn send_oob(&self, buf: &[u8]) -> io::Result<usize> {
+        let len = cmp::min(buf.len(), c_int::MAX as usize) as c_int;
+        let n = unsafe { send(self.socket(), buf.as_ptr() as *const c_char, len, MSG_OOB) };
+        if n == SOCKET_ERROR {
+            Err(io::Error::from_raw_os_error(unsafe { WSAGetLastError() }))
+        } else {
+            Ok(n as usize)
+        }
+    }
+
+    pub(crate) fn socket(&self) -> SOCKET {
+        self.sock.as_raw_socket() as SOCKET
+    }
+
+    /// Replaces the registration, dropping a previous one deregisters it.
+    pub(crate) fn set_registration(&mut self, registration: Registration) {
+        self.registration = Some(registration);
+    }
+
+    pub(crate) fn registration(&self) -> Option<&Registration> {
+        self.registration.as_ref()
+    }
+
+    pub(crate) fn registration_mut(&mut self) -> Option<&mut Registration> {
+        self.registration.as_mut()
+    }
+
+    pub(crate) fn clear_registration(&mut self) {
+        self.registration = None;
+    }
+}
+
+impl Read for TcpStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.sock.read(buf)
+    }
+}
+
+impl Write for TcpStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.sock.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sock.flush()
+    }
+}
+
+impl AsRawSocket for TcpStream {
+    fn as_raw_socket(&self) -> RawSocket {
+        self.sock.as_raw_socket()
+    }
+}
@@ -0,0 +1,77 @@
+use crate::core::afd::HANDLE;
+use crate::core::base_socket::resolve_base_socket;
+use crate::core::library::{Init, Library, LibraryRef};
+use std::io;
+use std::mem::size_of;
+use winapi::shared::minwindef::{DWORD, LPVOID, MAKEWORD};
+use winapi::shared::ntdef::NULL;
+use winapi::um::winsock2::{
+    WSACleanup, WSAGetLastError, WSAIoctl, WSAStartup, SOCKET, SOCKET_ERROR, WSADATA,
+};
+
+/// Returns the base socket of `socket`, looking through layered service
+/// providers which intercept `SIO_BASE_HANDLE`.
+pub(crate) fn ws_get_base_socket(socket: &SOCKET) -> io::Result<SOCKET> {
+    resolve_base_socket(*socket as HANDLE, |socket, code| {
+        ws_ioctl_get_bsp_socket(socket as SOCKET, code).map(|socket| socket as HANDLE)
+    })
+    .map(|base_socket| base_socket as SOCKET)
+    .map_err(io::Error::from)
+}
+
+fn ws_ioctl_get_bsp_socket(socket: SOCKET, code: DWORD) -> io::Result<SOCKET> {
+    let mut bsp_socket: SOCKET = 0;
+    let mut bytes: DWORD = 0;
+
+    unsafe {
+        if SOCKET_ERROR
+            == WSAIoctl(
+                socket,
+                code,
+                NULL,
+                0,
+                &mut bsp_socket as *mut _ as LPVOID,
+                size_of::<SOCKET>() as DWORD,
+                &mut bytes as *mut _,
+                NULL as _,
+                None,
+            )
+        {
+            return Err(io::Error::from_raw_os_error(WSAGetLastError()));
+        }
+    }
+
+    Ok(bsp_socket)
+}
+
+pub(crate) fn ws_global_init() -> io::Result<()> {
+    let mut wsa_data = WSADATA::default();
+
+    let r = unsafe { WSAStartup(MAKEWORD(2, 2), &mut wsa_data as *mut _) };
+
+    match r {
+        0 => Ok(()),
+        _ => Err(io::Error::from_raw_os_error(r)),
+    }
+}
+
+/// `WSAStartup` and `WSACleanup`.
+pub(crate) struct Winsock;
+
+impl Init for Winsock {
+    fn startup(&self) -> io::Result<()> {
+        ws_global_init()
+    }
+
+    fn cleanup(&self) {
+        unsafe { WSACleanup() };
+    }
+}
+
+static WINSOCK: Library<Winsock> = Library::new(Winsock);
+
+/// Takes a reference to Winsock, which stays initialized until the last
+/// reference has been dropped.
+pub(crate) fn winsock() -> io::Result<LibraryRef<'static, Winsock>> {
+    WINSOCK.acquire()
+}
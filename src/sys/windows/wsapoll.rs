@@ -0,0 +1,265 @@
+use crate::core::afd::HANDLE;
+use crate::core::driver::{Completion, Driver, PollStart};
+use crate::core::key::Key;
+use crate::core::poll_once::millis_timeout;
+use crate::core::proactor::IoRequest;
+use crate::core::sock::PollResult;
+use crate::core::strategy::ZERO_BYTE_READ_EVENTS;
+use crate::core::translate::{afd_events_to_poll_events, poll_events_to_afd_events};
+use crate::core::user_event::Post;
+use miow::iocp::CompletionStatus;
+use miow::Overlapped;
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+use winapi::shared::minwindef::ULONG;
+use winapi::um::winsock2::{WSAGetLastError, WSAPoll, SOCKET, SOCKET_ERROR, WSAPOLLFD};
+
+/// How long `WSAPoll` blocks at once while user events exist. Nothing can
+/// interrupt the call, so triggers are only noticed in between.
+const USER_EVENT_SLICE: Duration = Duration::from_millis(10);
+
+/// User events posted from any thread, picked up by `wait`.
+#[derive(Default)]
+struct Posted(Mutex<VecDeque<usize>>);
+
+impl Post for Posted {
+    fn post(&self, id: usize) -> io::Result<()> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push_back(id);
+        Ok(())
+    }
+}
+
+/// A poll operation waiting for the next `WSAPoll` call.
+struct PendingPoll {
+    base_socket: HANDLE,
+    afd_events: u32,
+}
+
+/// Emulates AFD poll operations with `WSAPoll`, for systems without the AFD
+/// device. Operations are only checked while `wait` runs, every call polls
+/// all of them at once.
+pub(crate) struct WsaPollDriver {
+    pending: HashMap<usize, PendingPoll>,
+    completions: VecDeque<Completion>,
+    // Results of the completions handed out by `wait`, until `completion`
+    // picks them up.
+    results: HashMap<usize, PollResult>,
+    posted: Arc<Posted>,
+    next_helper: HANDLE,
+}
+
+impl WsaPollDriver {
+    pub(crate) fn new() -> WsaPollDriver {
+        WsaPollDriver {
+            pending: HashMap::new(),
+            completions: VecDeque::new(),
+            results: HashMap::new(),
+            posted: Arc::new(Posted::default()),
+            next_helper: 0,
+        }
+    }
+
+    /// Queues the completions of the user events posted since the last call.
+    fn take_posted(&mut self) {
+        let mut posted = self.posted.0.lock().unwrap_or_else(PoisonError::into_inner);
+        self.completions
+            .extend(posted.drain(..).map(|id| Completion::User { id }));
+    }
+
+    /// Polls until completions are queued or `timeout` has passed, in slices
+    /// short enough to notice user events as long as any exist.
+    fn poll_sliced(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            self.take_posted();
+            if !self.completions.is_empty() {
+                return Ok(());
+            }
+
+            let remaining =
+                deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            // Every user event holds a reference to the queue.
+            if Arc::strong_count(&self.posted) == 1 {
+                return self.poll(remaining);
+            }
+            if remaining == Some(Duration::from_millis(0)) {
+                return Ok(());
+            }
+            let slice = remaining.map_or(USER_EVENT_SLICE, |remaining| {
+                cmp::min(remaining, USER_EVENT_SLICE)
+            });
+            self.poll(Some(slice))?;
+        }
+    }
+
+    fn poll(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        let timeout = millis_timeout(timeout);
+
+        if self.pending.is_empty() {
+            // WSAPoll refuses an empty set, and nothing could wake us up.
+            match timeout {
+                -1 => loop {
+                    thread::park();
+                },
+                ms => thread::sleep(Duration::from_millis(ms as u64)),
+            }
+            return Ok(());
+        }
+
+        let ids: Vec<usize> = self.pending.keys().cloned().collect();
+        let mut fds: Vec<WSAPOLLFD> = ids
+            .iter()
+            .map(|id| {
+                let poll = &self.pending[id];
+                WSAPOLLFD {
+                    fd: poll.base_socket as SOCKET,
+                    events: afd_events_to_poll_events(poll.afd_events) as i16,
+                    revents: 0,
+                }
+            })
+            .collect();
+
+        let n = unsafe { WSAPoll(fds.as_mut_ptr(), fds.len() as ULONG, timeout) };
+        if n == SOCKET_ERROR {
+            return Err(io::Error::from_raw_os_error(unsafe { WSAGetLastError() }));
+        }
+
+        for (id, fd) in ids.into_iter().zip(fds) {
+            if fd.revents == 0 {
+                continue;
+            }
+            let poll = self.pending.remove(&id).unwrap();
+            let afd_events = poll_events_to_afd_events(fd.revents as u16) & poll.afd_events;
+            self.completions.push_back(Completion::Poll {
+                id,
+                result: PollResult::Events(afd_events),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+impl Driver for WsaPollDriver {
+    type Status = CompletionStatus;
+
+    fn open_helper(&mut self) -> io::Result<HANDLE> {
+        // There is no helper to open, only poll groups to tell apart.
+        self.next_helper += 4;
+        Ok(self.next_helper)
+    }
+
+    fn close_helper(&mut self, _helper: HANDLE) {}
+
+    fn start_poll(
+        &mut self,
+        _helper: HANDLE,
+        base_socket: HANDLE,
+        afd_events: u32,
+        id: usize,
+    ) -> io::Result<PollStart> {
+        let poll = PendingPoll {
+            base_socket,
+            afd_events,
+        };
+        self.pending.insert(id, poll);
+        Ok(PollStart::Pending)
+    }
+
+    fn cancel_poll(&mut self, _helper: HANDLE, id: usize) -> io::Result<()> {
+        if self.pending.remove(&id).is_some() {
+            self.completions.push_back(Completion::Poll {
+                id,
+                result: PollResult::Cancelled,
+            });
+        }
+        Ok(())
+    }
+
+    fn prepare_zero_read(&mut self, _socket: HANDLE) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn start_zero_read(&mut self, socket: HANDLE, id: usize) -> io::Result<PollStart> {
+        // `WSAPoll` takes any socket, a poll for readability does the job.
+        self.start_poll(0, socket, ZERO_BYTE_READ_EVENTS, id)
+    }
+
+    fn cancel_zero_read(&mut self, _socket: HANDLE, id: usize) -> io::Result<()> {
+        self.cancel_poll(0, id)
+    }
+
+    fn wait(
+        &mut self,
+        statuses: &mut [CompletionStatus],
+        timeout: Option<Duration>,
+    ) -> io::Result<usize> {
+        self.take_posted();
+        if self.completions.is_empty() {
+            self.poll_sliced(timeout)?;
+        }
+
+        let mut n = 0;
+        while n < statuses.len() {
+            // The packets look like those of the AFD backend, with the id in
+            // place of the OVERLAPPED.
+            let (key, id) = match self.completions.pop_front() {
+                Some(Completion::Poll { id, result }) => {
+                    self.results.insert(id, result);
+                    (Key::Afd, id)
+                }
+                Some(Completion::User { id }) => (Key::User, id),
+                Some(Completion::Wake) => (Key::Waker, 0),
+                // Nothing is ever associated with this driver.
+                Some(Completion::Io(_)) => continue,
+                None => break,
+            };
+            statuses[n] = CompletionStatus::new(0, key.raw(), id as *mut Overlapped);
+            n += 1;
+        }
+        Ok(n)
+    }
+
+    fn completion(&mut self, status: &CompletionStatus) -> Option<Completion> {
+        let id = status.overlapped() as usize;
+        match Key::from_raw(status.token()) {
+            Key::Afd => self
+                .results
+                .remove(&id)
+                .map(|result| Completion::Poll { id, result }),
+            Key::Waker => Some(Completion::Wake),
+            Key::User => Some(Completion::User { id }),
+            Key::Io | Key::ZeroRead | Key::Foreign(_) => None,
+        }
+    }
+
+    fn poster(&self) -> Arc<dyn Post> {
+        self.posted.clone()
+    }
+
+    fn associate(&mut self, _handle: HANDLE) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "completion based I/O needs the AFD backend",
+        ))
+    }
+
+    fn submit(&mut self, handle: HANDLE, _request: IoRequest) -> io::Result<()> {
+        self.associate(handle)
+    }
+
+    fn cancel_ios(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn ios_in_flight(&self) -> bool {
+        false
+    }
+}